@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use pyo3::{
+    exceptions::{PyImportError, PyTypeError, PyValueError},
+    intern,
+    prelude::*,
+    types::{PyBytes, PyString},
+};
+
+use crate::{temporal::NaiveDatetime, uuid::UuidFormat, Backend, TypeAffinity};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EncodingKind {
+    Json,
+    MsgPack,
+    Custom,
+}
+
+impl EncodingKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            EncodingKind::Json => "json",
+            EncodingKind::MsgPack => "msgpack",
+            EncodingKind::Custom => "custom",
+        }
+    }
+}
+
+/// Encoding of dict/list values stored in Blob columns, resolved into a pair of callables
+pub(crate) struct BlobEncoding {
+    pub(crate) kind: EncodingKind,
+    encode: PyObject,
+    decode: PyObject,
+}
+
+const ENCODING_EXPECTED: &str =
+    "expected \"json\", \"msgpack\" or a tuple of (encode, decode) callables";
+
+impl BlobEncoding {
+    pub(crate) fn json(py: Python<'_>) -> PyResult<Self> {
+        let json = py.import(intern!(py, "json"))?;
+        Ok(BlobEncoding {
+            kind: EncodingKind::Json,
+            encode: json.getattr(intern!(py, "dumps"))?.unbind(),
+            decode: json.getattr(intern!(py, "loads"))?.unbind(),
+        })
+    }
+
+    /// Uses msgspec if available, the msgpack package otherwise
+    fn msgpack(py: Python<'_>) -> PyResult<Self> {
+        let (encode, decode) = if let Ok(msgspec) = py.import(intern!(py, "msgspec.msgpack")) {
+            (
+                msgspec.getattr(intern!(py, "encode"))?,
+                msgspec.getattr(intern!(py, "decode"))?,
+            )
+        } else if let Ok(msgpack) = py.import(intern!(py, "msgpack")) {
+            (
+                msgpack.getattr(intern!(py, "packb"))?,
+                msgpack.getattr(intern!(py, "unpackb"))?,
+            )
+        } else {
+            return Err(PyImportError::new_err(
+                "The msgpack encoding requires msgspec or msgpack to be installed",
+            ));
+        };
+        Ok(BlobEncoding {
+            kind: EncodingKind::MsgPack,
+            encode: encode.unbind(),
+            decode: decode.unbind(),
+        })
+    }
+
+    /// `"json"`, `"msgpack"` or a tuple of `(encode, decode)` callables
+    ///
+    /// Custom encoders return bytes (or str, stored as UTF-8), decoders receive the stored bytes.
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let py = value.py();
+        if let Ok(name) = value.downcast::<PyString>() {
+            return match name.to_str()? {
+                "json" => Self::json(py),
+                "msgpack" => Self::msgpack(py),
+                other => Err(PyValueError::new_err(format!(
+                    "Unknown encoding {other:?}, {ENCODING_EXPECTED}"
+                ))),
+            };
+        }
+
+        let invalid =
+            || PyTypeError::new_err(format!("Invalid encoding {value}, {ENCODING_EXPECTED}"));
+        let (encode, decode): (Bound<'_, PyAny>, Bound<'_, PyAny>) =
+            value.extract().map_err(|_| invalid())?;
+        if !encode.is_callable() || !decode.is_callable() {
+            return Err(invalid());
+        }
+        Ok(BlobEncoding {
+            kind: EncodingKind::Custom,
+            encode: encode.unbind(),
+            decode: decode.unbind(),
+        })
+    }
+
+    /// JSON is stored as native `jsonb` on postgres, bound as text and cast in the query
+    pub(crate) fn is_jsonb(&self, backend: Backend) -> bool {
+        self.kind == EncodingKind::Json && backend == Backend::Postgres
+    }
+
+    /// Encodes `value` into bytes, or into a str that is bound as text for `jsonb`
+    pub(crate) fn encode<'py>(&self, value: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+        let encoded = self.encode.bind(value.py()).call1((value,))?;
+        if !encoded.is_instance_of::<PyBytes>() && !encoded.is_instance_of::<PyString>() {
+            return Err(PyTypeError::new_err(format!(
+                "Encoder must return bytes or str, got {}",
+                encoded.get_type().qualname()?
+            )));
+        }
+        Ok(encoded)
+    }
+
+    pub(crate) fn decode(&self, value: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+        Ok(self.decode.bind(value.py()).call1((value,))?.unbind())
+    }
+}
+
+/// Per database options for converting python values to and from sql values
+pub(crate) struct Codec {
+    pub(crate) backend: Backend,
+    /// Encoding of dict/list parameters and of fields without their own `encoding`
+    pub(crate) blob_encoding: Arc<BlobEncoding>,
+    pub(crate) naive_datetime: NaiveDatetime,
+    pub(crate) uuid_format: UuidFormat,
+}
+
+impl Codec {
+    /// Affinity of UUIDs, which are only stored as bytes on sqlite with `UuidFormat::Bytes`
+    pub(crate) fn uuid_affinity(&self) -> TypeAffinity {
+        match (self.backend, self.uuid_format) {
+            (Backend::Sqlite, UuidFormat::Bytes) => TypeAffinity::Blob,
+            _ => TypeAffinity::Text,
+        }
+    }
+}
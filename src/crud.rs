@@ -0,0 +1,371 @@
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+};
+use sqlx::Arguments;
+
+use crate::{
+    codec::Codec,
+    ddl::quote_ident,
+    error::ProgrammingError,
+    model::{FieldDef, RegisteredModel},
+    params::{bind_field, placeholder, QueryArgs},
+    Backend,
+};
+
+/// Bind parameters per statement, sqlite's default `SQLITE_MAX_VARIABLE_NUMBER` (postgres allows 65535)
+const MAX_BIND_PARAMS: usize = 32766;
+
+/// ` RETURNING <primary key>`
+fn returning_pk(model: &RegisteredModel) -> PyResult<String> {
+    let pk = model.primary_key_field().ok_or_else(|| {
+        ProgrammingError::new_err(format!(
+            "Table {} has no primary key to return",
+            model.table_name
+        ))
+    })?;
+    Ok(format!(" RETURNING {}", quote_ident(pk.column())))
+}
+
+/// Placeholder of a field's value, cast from text for `FieldDef::pg_text_type`
+fn field_placeholder(field: &FieldDef, backend: Backend, idx: usize) -> String {
+    let placeholder = placeholder(backend, idx);
+    match field.pg_text_type() {
+        Some(sql_type) if backend == Backend::Postgres => format!("{placeholder}::{sql_type}"),
+        _ => placeholder,
+    }
+}
+
+fn column_list(model: &RegisteredModel) -> String {
+    model
+        .schema
+        .iter()
+        .map(|v| quote_ident(v.column()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Columns to select, those of `FieldDef::pg_text_type` as text since the Any driver can't decode them
+fn select_list(model: &RegisteredModel, backend: Backend) -> String {
+    model
+        .schema
+        .iter()
+        .map(|v| {
+            let column = quote_ident(v.column());
+            match v.pg_text_type() {
+                Some(_) if backend == Backend::Postgres => format!("{column}::text AS {column}"),
+                _ => column,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Wraps a model query on postgres to select the columns of `FieldDef::pg_text_type` fields as text
+///
+/// The query then has to return every column of the model.
+pub(crate) fn model_query(model: &RegisteredModel, backend: Backend, query: String) -> String {
+    if backend != Backend::Postgres || model.schema.iter().all(|v| v.pg_text_type().is_none()) {
+        return query;
+    }
+    // The newline ends a trailing `--` comment
+    let query = query.trim_end().trim_end_matches(';');
+    format!("SELECT {} FROM ({query}\n) q", select_list(model, backend))
+}
+
+/// `UPDATE` of `fields` (bound first) by primary key (bound last)
+fn update_sql(
+    model: &RegisteredModel,
+    pk: &FieldDef,
+    backend: Backend,
+    fields: &[&FieldDef],
+) -> String {
+    let assignments = fields
+        .iter()
+        .enumerate()
+        .map(|(idx, v)| {
+            format!(
+                "{} = {}",
+                quote_ident(v.column()),
+                field_placeholder(v, backend, idx)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "UPDATE {} SET {assignments} WHERE {} = {}",
+        quote_ident(&model.table_name),
+        quote_ident(pk.column()),
+        placeholder(backend, fields.len())
+    )
+}
+
+/// Statements by primary key, built once at registration
+pub(crate) struct ModelStatements {
+    /// `SELECT` of all columns
+    select: String,
+    /// `UPDATE` of all other columns, `None` if the primary key is the only column
+    update: Option<String>,
+    delete: String,
+    /// `INSERT` of all columns, updating all other columns on a conflicting primary key
+    upsert: String,
+}
+
+impl ModelStatements {
+    pub(crate) fn new(model: &RegisteredModel, backend: Backend) -> Option<Self> {
+        let pk = model.primary_key_field()?;
+        let table = quote_ident(&model.table_name);
+        let pk_column = quote_ident(pk.column());
+        let columns = column_list(model);
+        let others: Vec<&FieldDef> = model
+            .schema
+            .iter()
+            .filter(|v| !model.is_primary_key(v))
+            .collect();
+
+        let conflict = match others.is_empty() {
+            true => "DO NOTHING".to_owned(),
+            false => format!(
+                "DO UPDATE SET {}",
+                others
+                    .iter()
+                    .map(|v| format!("{0} = excluded.{0}", quote_ident(v.column())))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let values = model
+            .schema
+            .iter()
+            .enumerate()
+            .map(|(idx, v)| field_placeholder(v, backend, idx))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Some(ModelStatements {
+            select: format!(
+                "SELECT {} FROM {table} WHERE {pk_column} = {}",
+                select_list(model, backend),
+                placeholder(backend, 0)
+            ),
+            update: (!others.is_empty()).then(|| update_sql(model, pk, backend, &others)),
+            delete: format!(
+                "DELETE FROM {table} WHERE {pk_column} = {}",
+                placeholder(backend, 0)
+            ),
+            upsert: format!(
+                "INSERT INTO {table} ({columns}) VALUES ({values}) ON CONFLICT ({pk_column}) {conflict}"
+            ),
+        })
+    }
+}
+
+fn statements(model: &RegisteredModel) -> PyResult<(&ModelStatements, &FieldDef)> {
+    match (&model.statements, model.primary_key_field()) {
+        (Some(statements), Some(pk)) => Ok((statements, pk)),
+        _ => Err(ProgrammingError::new_err(format!(
+            "Table {} has no primary key",
+            model.table_name
+        ))),
+    }
+}
+
+/// Primary key of `instance`, which must be set
+fn instance_pk<'py>(
+    model: &RegisteredModel,
+    pk: &FieldDef,
+    instance: &Bound<'py, PyAny>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let value = instance.getattr(pk.name.as_str())?;
+    if value.is_none() {
+        return Err(PyValueError::new_err(format!(
+            "Primary key {} of {} instance is not set",
+            pk.name,
+            model.model.bind(instance.py()).qualname()?
+        )));
+    }
+    Ok(value)
+}
+
+/// `SELECT` of the row with primary key `pk`
+pub(crate) fn select_statement(
+    model: &RegisteredModel,
+    codec: &Codec,
+    pk: &Bound<'_, PyAny>,
+) -> PyResult<(String, QueryArgs)> {
+    let (statements, pk_field) = statements(model)?;
+    let mut args = QueryArgs::default();
+    bind_field(&mut args, pk_field, pk, codec)?;
+    Ok((statements.select.clone(), args))
+}
+
+/// `DELETE` of the row with primary key `pk`
+pub(crate) fn delete_statement(
+    model: &RegisteredModel,
+    codec: &Codec,
+    pk: &Bound<'_, PyAny>,
+) -> PyResult<(String, QueryArgs)> {
+    let (statements, pk_field) = statements(model)?;
+    let mut args = QueryArgs::default();
+    bind_field(&mut args, pk_field, pk, codec)?;
+    Ok((statements.delete.clone(), args))
+}
+
+/// `UPDATE` of the row of `instance`, of all `fields` (names) or otherwise every non primary key field
+pub(crate) fn update_statement(
+    model: &RegisteredModel,
+    codec: &Codec,
+    instance: &Bound<'_, PyAny>,
+    fields: Option<Vec<String>>,
+) -> PyResult<(String, QueryArgs)> {
+    let (statements, pk_field) = statements(model)?;
+    let pk = instance_pk(model, pk_field, instance)?;
+
+    let (query, fields) = match fields {
+        None => {
+            let query = statements.update.clone().ok_or_else(|| {
+                PyValueError::new_err(format!(
+                    "Table {} has no columns to update",
+                    model.table_name
+                ))
+            })?;
+            let fields: Vec<&FieldDef> = model
+                .schema
+                .iter()
+                .filter(|v| !model.is_primary_key(v))
+                .collect();
+            (query, fields)
+        }
+        Some(names) => {
+            let mut fields = Vec::with_capacity(names.len());
+            for name in names.iter() {
+                let field = model
+                    .schema
+                    .iter()
+                    .find(|v| &v.name == name)
+                    .ok_or_else(|| {
+                        PyValueError::new_err(format!(
+                            "Unknown field {name:?} of table {}",
+                            model.table_name
+                        ))
+                    })?;
+                if model.is_primary_key(field) {
+                    return Err(PyValueError::new_err(format!(
+                        "Primary key {name:?} can't be updated"
+                    )));
+                }
+                fields.push(field);
+            }
+            if fields.is_empty() {
+                return Err(PyValueError::new_err("No fields to update"));
+            }
+            (update_sql(model, pk_field, codec.backend, &fields), fields)
+        }
+    };
+
+    let mut args = QueryArgs::default();
+    args.reserve(fields.len() + 1, 0);
+    for field in fields {
+        bind_field(
+            &mut args,
+            field,
+            &instance.getattr(field.name.as_str())?,
+            codec,
+        )?;
+    }
+    bind_field(&mut args, pk_field, &pk, codec)?;
+    Ok((query, args))
+}
+
+/// `INSERT` of `instance` which updates the existing row on a conflicting primary key
+///
+/// Without a primary key (to be generated) this is a plain `INSERT`.
+pub(crate) fn upsert_statement(
+    model: &RegisteredModel,
+    codec: &Codec,
+    instance: &Bound<'_, PyAny>,
+) -> PyResult<(String, QueryArgs)> {
+    let (statements, pk_field) = statements(model)?;
+    if pk_field.options.autoincrement && instance.getattr(pk_field.name.as_str())?.is_none() {
+        let mut statements =
+            insert_statements(model, codec, std::slice::from_ref(instance), false)?;
+        return Ok(statements.remove(0));
+    }
+
+    let mut args = QueryArgs::default();
+    args.reserve(model.schema.len(), 0);
+    for field in model.schema.iter() {
+        bind_field(
+            &mut args,
+            field,
+            &instance.getattr(field.name.as_str())?,
+            codec,
+        )?;
+    }
+    Ok((statements.upsert.clone(), args))
+}
+
+/// Multi-row `INSERT` statements for `instances`, split to stay below `MAX_BIND_PARAMS`
+///
+/// Autoincrement primary keys that are `None` are generated by the database,
+/// sqlite does so for NULL while postgres needs `DEFAULT` in place of the parameter.
+pub(crate) fn insert_statements(
+    model: &RegisteredModel,
+    codec: &Codec,
+    instances: &[Bound<'_, PyAny>],
+    returning: bool,
+) -> PyResult<Vec<(String, QueryArgs)>> {
+    let Some(py) = instances.first().map(|v| v.py()) else {
+        return Ok(Vec::new());
+    };
+    let returning = match returning {
+        true => returning_pk(model)?,
+        false => String::new(),
+    };
+
+    let columns = column_list(model);
+    let rows_per_statement = (MAX_BIND_PARAMS / model.schema.len()).max(1);
+
+    let mut statements = Vec::new();
+    for chunk in instances.chunks(rows_per_statement) {
+        let mut sql = format!(
+            "INSERT INTO {} ({columns}) VALUES ",
+            quote_ident(&model.table_name)
+        );
+        let mut args = QueryArgs::default();
+        args.reserve(chunk.len() * model.schema.len(), 0);
+        let mut idx = 0;
+
+        for (i, instance) in chunk.iter().enumerate() {
+            if !instance.is_instance(model.model.bind(py))? {
+                return Err(PyTypeError::new_err(format!(
+                    "Expected instances of {}, got {}",
+                    model.model.bind(py).qualname()?,
+                    instance.get_type().qualname()?
+                )));
+            }
+
+            sql.push_str(if i == 0 { "(" } else { ", (" });
+            for (j, field) in model.schema.iter().enumerate() {
+                if j > 0 {
+                    sql.push_str(", ");
+                }
+                let value = instance.getattr(field.name.as_str())?;
+                if codec.backend == Backend::Postgres
+                    && field.options.autoincrement
+                    && value.is_none()
+                {
+                    sql.push_str("DEFAULT");
+                    continue;
+                }
+                bind_field(&mut args, field, &value, codec)?;
+                sql.push_str(&field_placeholder(field, codec.backend, idx));
+                idx += 1;
+            }
+            sql.push(')');
+        }
+        sql.push_str(&returning);
+        statements.push((sql, args));
+    }
+    Ok(statements)
+}
@@ -0,0 +1,224 @@
+use std::borrow::Cow;
+
+use pyo3::{prelude::*, types::PyBool, PyTypeInfo};
+
+use crate::{
+    decode::Converter,
+    model::{FieldDef, RegisteredModel},
+    Backend, TypeAffinity,
+};
+
+/// Quotes an identifier, valid for both sqlite and postgres
+pub(crate) fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quotes a string literal, valid for both sqlite and postgres
+pub(crate) fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Backend specific column type of a field
+pub(crate) fn column_type<'a>(
+    py: Python<'_>,
+    field: &'a FieldDef,
+    backend: Backend,
+) -> Cow<'a, str> {
+    let sql_type = &field.type_def.sql_type;
+    let is_decimal = matches!(field.converter, Converter::Decimal);
+    match backend {
+        // A NUMERIC column would turn decimal text into (lossy) floats
+        Backend::Sqlite if is_decimal => "TEXT".into(),
+        // int/float unions are REAL like on postgres, a NUMERIC column would return 1.0 as 1
+        Backend::Sqlite if sql_type.affinity == TypeAffinity::Numeric => "REAL".into(),
+        // Sqlite only knows type affinities, so their names are used as-is
+        Backend::Sqlite => sql_type.affinity.as_str().into(),
+        Backend::Postgres if is_decimal => match field.options.precision {
+            Some(precision) => {
+                format!("NUMERIC({precision}, {})", field.options.scale.unwrap_or(0)).into()
+            }
+            None => "NUMERIC".into(),
+        },
+        Backend::Postgres => field
+            .pg_text_type()
+            .unwrap_or(match sql_type.affinity {
+                // bools are bound as proper booleans on postgres
+                TypeAffinity::Integer
+                    if field.type_def.py_type.bind(py).is(&PyBool::type_object(py)) =>
+                {
+                    "BOOLEAN"
+                }
+                // ints are always bound as i64
+                TypeAffinity::Integer => "BIGINT",
+                // The Any driver can't decode NUMERIC, so int/float unions are stored as doubles
+                TypeAffinity::Real | TypeAffinity::Numeric => "DOUBLE PRECISION",
+                TypeAffinity::Text => "TEXT",
+                TypeAffinity::Blob => "BYTEA",
+            })
+            .into(),
+    }
+}
+
+/// Column definition within `CREATE TABLE`
+pub(crate) fn column_def(
+    py: Python<'_>,
+    model: &RegisteredModel,
+    field: &FieldDef,
+    backend: Backend,
+) -> String {
+    let mut def = format!(
+        "{} {}",
+        quote_ident(field.column()),
+        column_type(py, field, backend)
+    );
+
+    let is_pk = model.is_primary_key(field);
+    if is_pk {
+        match (backend, field.options.autoincrement) {
+            (Backend::Sqlite, true) => def.push_str(" PRIMARY KEY AUTOINCREMENT"),
+            (Backend::Postgres, true) => {
+                def.push_str(" GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY")
+            }
+            (_, false) => def.push_str(" PRIMARY KEY"),
+        }
+    } else if field.options.unique {
+        def.push_str(" UNIQUE");
+    }
+    // Autoincrement keys may be annotated as optional, but are never null
+    if !field.type_def.sql_type.nullable || is_pk {
+        def.push_str(" NOT NULL");
+    }
+    if let Some(default) = &field.options.default_sql {
+        def.push_str(" DEFAULT ");
+        def.push_str(default);
+    }
+    // A native postgres enum already restricts the values
+    let native_enum = backend == Backend::Postgres && field.pg_enum.is_some();
+    if let (true, false, Some(choices)) =
+        (field.options.check, native_enum, &field.type_def.choices)
+    {
+        let values = choices
+            .iter()
+            .map(|v| v.sql_literal(backend))
+            .collect::<Vec<_>>()
+            .join(", ");
+        def.push_str(&format!(
+            " CHECK ({} IN ({values}))",
+            quote_ident(field.column())
+        ));
+    }
+    def
+}
+
+/// `CREATE TYPE` of a native postgres enum (see `FieldDef::pg_enum`)
+///
+/// Postgres has no `CREATE TYPE IF NOT EXISTS`, so `if_not_exists` ignores the error of an existing type.
+pub(crate) fn create_enum_type(field: &FieldDef, if_not_exists: bool) -> Option<String> {
+    let (Some(name), Some(choices)) = (&field.pg_enum, &field.type_def.choices) else {
+        return None;
+    };
+    let values = choices
+        .iter()
+        .map(|v| v.sql_literal(Backend::Postgres))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let create = format!("CREATE TYPE {name} AS ENUM ({values})");
+    Some(match if_not_exists {
+        true => format!("DO $$ BEGIN {create}; EXCEPTION WHEN duplicate_object THEN NULL; END $$"),
+        false => create,
+    })
+}
+
+/// `ALTER TYPE` statements adding `labels` to the native postgres enum of a field
+///
+/// Values can only be added, and can't be used within the transaction that adds them.
+pub(crate) fn add_enum_values(field: &FieldDef, labels: &[String]) -> Vec<String> {
+    let Some(name) = &field.pg_enum else {
+        return Vec::new();
+    };
+    labels
+        .iter()
+        .map(|v| {
+            format!(
+                "ALTER TYPE {name} ADD VALUE IF NOT EXISTS {}",
+                quote_literal(v)
+            )
+        })
+        .collect()
+}
+
+/// Fields that get a `CREATE INDEX`, unique and primary key columns are already indexed
+pub(crate) fn indexed_fields(model: &RegisteredModel) -> impl Iterator<Item = &FieldDef> {
+    model
+        .schema
+        .iter()
+        .filter(|v| v.options.index && !v.options.unique && !model.is_primary_key(v))
+}
+
+pub(crate) fn index_name(model: &RegisteredModel, field: &FieldDef) -> String {
+    format!("ix_{}_{}", model.table_name, field.column())
+}
+
+pub(crate) fn create_index(
+    model: &RegisteredModel,
+    field: &FieldDef,
+    if_not_exists: bool,
+) -> String {
+    format!(
+        "CREATE INDEX {}{} ON {} ({})",
+        if if_not_exists { "IF NOT EXISTS " } else { "" },
+        quote_ident(&index_name(model, field)),
+        quote_ident(&model.table_name),
+        quote_ident(field.column())
+    )
+}
+
+/// `CREATE TABLE` of the model's columns under the name `table`
+pub(crate) fn create_table_as(
+    py: Python<'_>,
+    model: &RegisteredModel,
+    backend: Backend,
+    table: &str,
+    if_not_exists: bool,
+) -> String {
+    let columns = model
+        .schema
+        .iter()
+        .map(|field| format!("    {}", column_def(py, model, field, backend)))
+        .collect::<Vec<_>>()
+        .join(",\n");
+
+    format!(
+        "CREATE TABLE {}{} (\n{columns}\n)",
+        if if_not_exists { "IF NOT EXISTS " } else { "" },
+        quote_ident(table)
+    )
+}
+
+/// `CREATE TABLE` followed by the `CREATE INDEX` statements of the model,
+/// preceded by the `CREATE TYPE` of its enums on postgres
+pub(crate) fn create_table(
+    py: Python<'_>,
+    model: &RegisteredModel,
+    backend: Backend,
+    if_not_exists: bool,
+) -> Vec<String> {
+    let mut statements = Vec::new();
+    if backend == Backend::Postgres {
+        statements.extend(
+            model
+                .schema
+                .iter()
+                .filter_map(|v| create_enum_type(v, if_not_exists)),
+        );
+    }
+    statements.push(create_table_as(
+        py,
+        model,
+        backend,
+        &model.table_name,
+        if_not_exists,
+    ));
+    statements.extend(indexed_fields(model).map(|v| create_index(model, v, if_not_exists)));
+    statements
+}
@@ -0,0 +1,23 @@
+use pyo3::{prelude::*, types::PyType};
+
+use crate::typeref::DECIMAL_TYPE;
+
+pub(crate) fn decimal_type(py: Python<'_>) -> Bound<'_, PyType> {
+    // SAFETY: initialized at module init and lives as long as the interpreter
+    unsafe { Bound::from_borrowed_ptr(py, DECIMAL_TYPE.cast()).downcast_into_unchecked() }
+}
+
+pub(crate) fn is_decimal_type(ptype: &Bound<'_, PyType>) -> bool {
+    // SAFETY: see `decimal_type`
+    unsafe { pyo3::ffi::PyType_IsSubtype(ptype.as_type_ptr(), DECIMAL_TYPE) != 0 }
+}
+
+/// Decimals are exchanged as text, which is exact unlike floats
+pub(crate) fn to_text(value: &Bound<'_, PyAny>) -> PyResult<String> {
+    Ok(value.str()?.to_str()?.to_owned())
+}
+
+/// `decimal.Decimal` of its text, numbers stored by other means are converted by their text as well
+pub(crate) fn parse(value: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+    Ok(decimal_type(value.py()).call1((value.str()?,))?.unbind())
+}
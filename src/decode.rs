@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use pyo3::{
+    prelude::*,
+    types::{PyBool, PyBytes, PyString, PyTuple, PyType},
+    PyTypeInfo,
+};
+use sqlx::{any::AnyRow, Column, Row};
+
+use crate::{
+    codec::{BlobEncoding, Codec},
+    decimal::{self, is_decimal_type},
+    enums::is_enum_type,
+    model::{RegisteredModel, TypeDef},
+    temporal::Temporal,
+    uuid::{self, is_uuid_type},
+    value_to_ptr, TypeAffinity,
+};
+
+/// Conversion from a column value to the python type of a field, picked at registration
+pub(crate) enum Converter {
+    /// The value as returned by `value_to_ptr`
+    Native,
+    /// Integers to bool, sqlite has no boolean type
+    Bool,
+    /// Dicts and lists, decoded from the stored bytes (or `jsonb` text)
+    Encoded(Arc<BlobEncoding>),
+    /// ISO 8601 text (selected as text on postgres) to `datetime`, `date` or `time`
+    Temporal(Temporal),
+    /// 16 bytes or text to `uuid.UUID`
+    Uuid,
+    /// Text (or a number) to `decimal.Decimal`
+    Decimal,
+    /// Stored value to the member of the enum class
+    Enum(Py<PyType>),
+}
+
+impl Converter {
+    /// `encoding` is used by non-bytes Blob fields
+    pub(crate) fn for_type(
+        py: Python<'_>,
+        type_def: &TypeDef,
+        encoding: &Arc<BlobEncoding>,
+    ) -> PyResult<Self> {
+        let py_type = type_def.py_type.bind(py);
+        if is_uuid_type(py_type) {
+            return Ok(Converter::Uuid);
+        }
+        if is_decimal_type(py_type) {
+            return Ok(Converter::Decimal);
+        }
+        if is_enum_type(py_type) {
+            return Ok(Converter::Enum(py_type.clone().unbind()));
+        }
+        Ok(match type_def.sql_type.affinity {
+            TypeAffinity::Integer if py_type.is(&PyBool::type_object(py)) => Converter::Bool,
+            TypeAffinity::Blob if !py_type.is_subclass_of::<PyBytes>()? => {
+                Converter::Encoded(encoding.clone())
+            }
+            TypeAffinity::Text => match Temporal::of_type(py_type) {
+                Some(temporal) => Converter::Temporal(temporal),
+                None => Converter::Native,
+            },
+            _ => Converter::Native,
+        })
+    }
+
+    fn convert(
+        &self,
+        py: Python<'_>,
+        row: &AnyRow,
+        index: usize,
+        codec: &Codec,
+    ) -> PyResult<PyObject> {
+        // SAFETY: `value_to_ptr` returns a new (or incref'd immortal) reference
+        let value = unsafe { PyObject::from_owned_ptr(py, value_to_ptr(row, index)?) };
+        match self {
+            Converter::Native => Ok(value),
+            _ if value.is_none(py) => Ok(value),
+            Converter::Bool => Ok(PyBool::new(py, value.is_truthy(py)?)
+                .to_owned()
+                .into_any()
+                .unbind()),
+            Converter::Encoded(encoding) => encoding.decode(value.bind(py)),
+            Converter::Temporal(temporal) => temporal.parse(value.bind(py), &codec.naive_datetime),
+            Converter::Uuid => uuid::parse(value.bind(py)),
+            Converter::Decimal => decimal::parse(value.bind(py)),
+            Converter::Enum(enum_type) => Ok(enum_type.bind(py).call1((value,))?.unbind()),
+        }
+    }
+}
+
+/// Maps the columns of a result set onto the fields of a registered model
+///
+/// Built from the first row, as all rows of a result set share their columns.
+pub(crate) struct ModelDecoder {
+    model: Arc<RegisteredModel>,
+    /// (column index, field index) for every field in the result set
+    columns: Vec<(usize, usize)>,
+    /// Field names in the order of `columns`, passed as keywords to the model constructor
+    kwnames: Py<PyTuple>,
+}
+
+impl ModelDecoder {
+    /// Fields without a matching column are left to the model's defaults
+    pub(crate) fn new(py: Python<'_>, model: Arc<RegisteredModel>, row: &AnyRow) -> PyResult<Self> {
+        let columns: Vec<(usize, usize)> = model
+            .schema
+            .iter()
+            .enumerate()
+            .filter_map(|(field_idx, field)| {
+                row.columns()
+                    .iter()
+                    .position(|c| c.name() == field.column())
+                    .map(|col_idx| (col_idx, field_idx))
+            })
+            .collect();
+        let kwnames = PyTuple::new(
+            py,
+            columns
+                .iter()
+                .map(|(_, field_idx)| PyString::intern(py, &model.schema[*field_idx].name)),
+        )?
+        .unbind();
+
+        Ok(ModelDecoder {
+            model,
+            columns,
+            kwnames,
+        })
+    }
+
+    /// Calls the model with the converted values of `row` as keyword arguments
+    pub(crate) fn decode(&self, py: Python<'_>, row: &AnyRow) -> PyResult<PyObject> {
+        let values = self
+            .columns
+            .iter()
+            .map(|(col_idx, field_idx)| {
+                self.model.schema[*field_idx].converter.convert(
+                    py,
+                    row,
+                    *col_idx,
+                    &self.model.codec,
+                )
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        let ptrs: Vec<*mut pyo3::ffi::PyObject> = values.iter().map(|v| v.as_ptr()).collect();
+
+        // SAFETY: `ptrs` are kept alive by `values`, there are no positional arguments so every value
+        //  is matched up with a name of `kwnames`
+        unsafe {
+            let obj = pyo3::ffi::PyObject_Vectorcall(
+                self.model.model.as_ptr(),
+                ptrs.as_ptr(),
+                0,
+                self.kwnames.as_ptr(),
+            );
+            Ok(Bound::from_owned_ptr_or_err(py, obj)?.unbind())
+        }
+    }
+}
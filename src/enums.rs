@@ -0,0 +1,99 @@
+use eyre::Result;
+use pyo3::{
+    intern,
+    prelude::*,
+    types::{PyBool, PyInt, PyString, PyTuple, PyType},
+};
+
+use crate::{ddl::quote_literal, typeref::ENUM_TYPE, Backend, TypeAffinity};
+
+pub(crate) fn is_enum_type(ptype: &Bound<'_, PyType>) -> bool {
+    // SAFETY: initialized at module init and lives as long as the interpreter
+    unsafe { pyo3::ffi::PyType_IsSubtype(ptype.as_type_ptr(), ENUM_TYPE) != 0 }
+}
+
+/// Enum members are stored (and bound) by their value
+pub(crate) fn enum_value<'py>(value: &Bound<'py, PyAny>) -> PyResult<Option<Bound<'py, PyAny>>> {
+    match is_enum_type(&value.get_type()) {
+        true => Ok(Some(value.getattr(intern!(value.py(), "value"))?)),
+        false => Ok(None),
+    }
+}
+
+/// Allowed value of an enum or `Literal` field
+pub(crate) enum Choice {
+    Bool(bool),
+    Int(i64),
+    Text(String),
+}
+
+impl Choice {
+    fn from_py(value: &Bound<'_, PyAny>) -> Result<Self> {
+        let value = enum_value(value)?.unwrap_or_else(|| value.clone());
+        // bool is a subclass of int, so it's checked first
+        if let Ok(v) = value.downcast::<PyBool>() {
+            Ok(Choice::Bool(v.is_true()))
+        } else if value.is_instance_of::<PyInt>() {
+            Ok(Choice::Int(value.extract()?))
+        } else if let Ok(v) = value.downcast::<PyString>() {
+            Ok(Choice::Text(v.to_str()?.to_owned()))
+        } else {
+            Err(eyre::eyre!(
+                "Unsupported value {value}, only str, int and bool values are supported"
+            ))
+        }
+    }
+
+    pub(crate) fn sql_literal(&self, backend: Backend) -> String {
+        match (self, backend) {
+            (Choice::Bool(v), Backend::Sqlite) => (*v as i64).to_string(),
+            (Choice::Bool(v), Backend::Postgres) => v.to_string().to_ascii_uppercase(),
+            (Choice::Int(v), _) => v.to_string(),
+            (Choice::Text(v), _) => quote_literal(v),
+        }
+    }
+}
+
+/// Values of an enum's members (aliases excluded), in definition order
+pub(crate) fn enum_choices(ptype: &Bound<'_, PyType>) -> Result<Vec<Choice>> {
+    let choices = ptype
+        .try_iter()?
+        .map(|member| Choice::from_py(&member?))
+        .collect::<Result<Vec<_>>>()?;
+    if choices.is_empty() {
+        return Err(eyre::eyre!("Enum {} has no members", ptype.qualname()?));
+    }
+    Ok(choices)
+}
+
+/// `Some(values)` when `anno` is `Literal[*values]`
+pub(crate) fn literal_args<'py>(anno: &Bound<'py, PyAny>) -> PyResult<Option<Bound<'py, PyTuple>>> {
+    let py = anno.py();
+    let typing_mod = py.import(intern!(py, "typing"))?;
+    let origin = typing_mod
+        .getattr(intern!(py, "get_origin"))?
+        .call1((anno,))?;
+
+    if origin.is(&typing_mod.getattr(intern!(py, "Literal"))?) {
+        return Ok(Some(
+            anno.getattr(intern!(py, "__args__"))?.downcast_into()?,
+        ));
+    }
+    Ok(None)
+}
+
+/// `Literal` values as choices
+pub(crate) fn literal_choices(args: &Bound<'_, PyTuple>) -> Result<Vec<Choice>> {
+    args.iter().map(|v| Choice::from_py(&v)).collect()
+}
+
+/// Affinity shared by all `choices`, bools and ints are both stored as integers
+pub(crate) fn choices_affinity(choices: &[Choice]) -> Result<TypeAffinity> {
+    if choices.iter().all(|v| matches!(v, Choice::Text(_))) {
+        Ok(TypeAffinity::Text)
+    } else if choices.iter().all(|v| !matches!(v, Choice::Text(_))) {
+        Ok(TypeAffinity::Integer)
+    } else {
+        Err(eyre::eyre!("values mix str and int"))
+    }
+}
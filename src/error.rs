@@ -0,0 +1,171 @@
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyIndexError, PyKeyError},
+    prelude::*,
+};
+use sqlx::{error::ErrorKind, sqlite::SqliteError};
+
+// DB-API 2.0 (PEP 249) exception hierarchy
+create_exception!(pysqlx, Error, PyException);
+create_exception!(pysqlx, InterfaceError, Error);
+create_exception!(pysqlx, DatabaseError, Error);
+create_exception!(pysqlx, DataError, DatabaseError);
+create_exception!(pysqlx, OperationalError, DatabaseError);
+create_exception!(pysqlx, IntegrityError, DatabaseError);
+create_exception!(pysqlx, ProgrammingError, DatabaseError);
+// `fetch_one` of a query without rows
+create_exception!(pysqlx, RowNotFoundError, ProgrammingError);
+
+/// DB-API exception subclasses of `DatabaseError` that are picked by error code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorClass {
+    Data,
+    Operational,
+    Integrity,
+    Programming,
+}
+
+impl ErrorClass {
+    fn new_err(self, msg: String) -> PyErr {
+        match self {
+            ErrorClass::Data => DataError::new_err(msg),
+            ErrorClass::Operational => OperationalError::new_err(msg),
+            ErrorClass::Integrity => IntegrityError::new_err(msg),
+            ErrorClass::Programming => ProgrammingError::new_err(msg),
+        }
+    }
+}
+
+/// Maps a SQLite (extended) result code, where the primary code is the low byte
+fn from_sqlite_code(code: i32) -> Option<ErrorClass> {
+    match code & 0xff {
+        // SQLITE_ERROR: syntax errors, missing tables, etc.
+        1 => Some(ErrorClass::Programming),
+        // SQLITE_CONSTRAINT
+        19 => Some(ErrorClass::Integrity),
+        // SQLITE_TOOBIG, SQLITE_MISMATCH, SQLITE_RANGE
+        18 | 20 | 25 => Some(ErrorClass::Data),
+        // SQLITE_BUSY, SQLITE_LOCKED, SQLITE_NOMEM, SQLITE_READONLY, SQLITE_IOERR, SQLITE_CORRUPT, SQLITE_FULL, SQLITE_CANTOPEN
+        5..=8 | 10 | 11 | 13 | 14 => Some(ErrorClass::Operational),
+        _ => None,
+    }
+}
+
+/// Maps a 5 character Postgres SQLSTATE by its class (the first 2 characters)
+fn from_sqlstate(code: &str) -> Option<ErrorClass> {
+    match code.get(..2)? {
+        "22" => Some(ErrorClass::Data),
+        "23" => Some(ErrorClass::Integrity),
+        "42" | "0A" => Some(ErrorClass::Programming),
+        "08" | "53" | "55" | "57" | "58" => Some(ErrorClass::Operational),
+        _ => None,
+    }
+}
+
+/// Maps the code of a database error using the table of its driver
+///
+/// SQLSTATEs are mostly digits as well, so the code alone can't tell the tables apart.
+fn from_db_error(db_err: &dyn sqlx::error::DatabaseError) -> Option<ErrorClass> {
+    let code = db_err.code()?;
+    match db_err.try_downcast_ref::<SqliteError>() {
+        Some(_) => from_sqlite_code(code.parse().ok()?),
+        None => from_sqlstate(&code),
+    }
+}
+
+/// Converts a `sqlx::Error` into the matching python exception
+pub(crate) fn to_pyerr(err: sqlx::Error) -> PyErr {
+    let msg = err.to_string();
+    to_pyerr_with_msg(err, msg)
+}
+
+/// `to_pyerr` with `context` prepended to the message
+pub(crate) fn to_pyerr_context(err: sqlx::Error, context: &str) -> PyErr {
+    let msg = format!("{context}: {err}");
+    to_pyerr_with_msg(err, msg)
+}
+
+fn to_pyerr_with_msg(err: sqlx::Error, msg: String) -> PyErr {
+    match err {
+        sqlx::Error::Database(db_err) => {
+            let class = match db_err.kind() {
+                ErrorKind::UniqueViolation
+                | ErrorKind::ForeignKeyViolation
+                | ErrorKind::NotNullViolation
+                | ErrorKind::CheckViolation => Some(ErrorClass::Integrity),
+                _ => from_db_error(db_err.as_ref()),
+            };
+            match class {
+                Some(class) => class.new_err(msg),
+                None => DatabaseError::new_err(msg),
+            }
+        }
+        sqlx::Error::Configuration(_)
+        | sqlx::Error::Protocol(_)
+        | sqlx::Error::AnyDriverError(_) => InterfaceError::new_err(msg),
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => OperationalError::new_err(msg),
+        sqlx::Error::TypeNotFound { .. } => ProgrammingError::new_err(msg),
+        sqlx::Error::RowNotFound => RowNotFoundError::new_err(msg),
+        sqlx::Error::ColumnNotFound(name) => PyKeyError::new_err(name),
+        sqlx::Error::ColumnIndexOutOfBounds { .. } => PyIndexError::new_err(msg),
+        sqlx::Error::ColumnDecode { .. } | sqlx::Error::Decode(_) | sqlx::Error::Encode(_) => {
+            DataError::new_err(msg)
+        }
+        _ => DatabaseError::new_err(msg),
+    }
+}
+
+pub(crate) fn add_exceptions(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("Error", py.get_type::<Error>())?;
+    m.add("InterfaceError", py.get_type::<InterfaceError>())?;
+    m.add("DatabaseError", py.get_type::<DatabaseError>())?;
+    m.add("DataError", py.get_type::<DataError>())?;
+    m.add("OperationalError", py.get_type::<OperationalError>())?;
+    m.add("IntegrityError", py.get_type::<IntegrityError>())?;
+    m.add("ProgrammingError", py.get_type::<ProgrammingError>())?;
+    m.add("RowNotFoundError", py.get_type::<RowNotFoundError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqlite_codes() {
+        assert_eq!(from_sqlite_code(1), Some(ErrorClass::Programming));
+        // SQLITE_CONSTRAINT_UNIQUE
+        assert_eq!(from_sqlite_code(2067), Some(ErrorClass::Integrity));
+        assert_eq!(from_sqlite_code(20), Some(ErrorClass::Data));
+        // SQLITE_BUSY_SNAPSHOT
+        assert_eq!(from_sqlite_code(517), Some(ErrorClass::Operational));
+        assert_eq!(from_sqlite_code(14), Some(ErrorClass::Operational));
+        // SQLITE_INTERRUPT
+        assert_eq!(from_sqlite_code(9), None);
+    }
+
+    #[test]
+    fn postgres_sqlstates() {
+        // syntax_error
+        assert_eq!(from_sqlstate("42601"), Some(ErrorClass::Programming));
+        // insufficient_privilege
+        assert_eq!(from_sqlstate("42501"), Some(ErrorClass::Programming));
+        // feature_not_supported
+        assert_eq!(from_sqlstate("0A000"), Some(ErrorClass::Programming));
+        // division_by_zero
+        assert_eq!(from_sqlstate("22012"), Some(ErrorClass::Data));
+        // unique_violation
+        assert_eq!(from_sqlstate("23505"), Some(ErrorClass::Integrity));
+        // connection_failure, lock_not_available
+        assert_eq!(from_sqlstate("08006"), Some(ErrorClass::Operational));
+        assert_eq!(from_sqlstate("55P03"), Some(ErrorClass::Operational));
+        // raise_exception
+        assert_eq!(from_sqlstate("P0001"), None);
+        assert_eq!(from_sqlstate("4"), None);
+    }
+}
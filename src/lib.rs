@@ -1,30 +1,66 @@
 #![cfg_attr(feature = "intrinsics", feature(core_intrinsics))]
 
-use futures::lock::Mutex;
-use std::{pin::Pin, str::FromStr, sync::OnceLock};
+use futures::lock::{Mutex, OwnedMutexGuard};
+use std::{
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
 
 use eyre::Result;
 use futures::TryStreamExt;
 use futures_core::stream::BoxStream;
-use hashbrown::HashMap;
 use pyo3::{
+    exceptions::{PyIndexError, PyKeyError, PyStopAsyncIteration, PyTypeError, PyValueError},
     ffi::PyTypeObject,
     intern,
     prelude::*,
-    types::{PyBytes, PyDict, PyInt, PyString, PyType},
+    sync::GILOnceCell,
+    types::{
+        PyBool, PyBytes, PyDate, PyDateTime, PyDict, PyFloat, PyInt, PyIterator, PyList, PySlice,
+        PyString, PyTime, PyTuple, PyType,
+    },
     PyTypeInfo,
 };
 use sqlx::{
-    any::{AnyConnectOptions, AnyRow, AnyValue},
-    AnyPool, Executor, Row, ValueRef,
+    any::{AnyConnectOptions, AnyPoolOptions, AnyQueryResult, AnyRow, AnyValue},
+    AnyConnection, AnyPool, Column, ColumnIndex, Executor, Row, ValueRef,
 };
 
 #[macro_use]
 mod str;
+mod codec;
+mod crud;
+mod ddl;
+mod decimal;
+mod decode;
+mod enums;
+mod error;
+mod migrate;
+mod model;
+mod params;
+mod rt;
+mod temporal;
+mod transaction;
 pub(crate) mod typeref;
+mod uuid;
 
+use codec::{BlobEncoding, Codec};
+use decode::ModelDecoder;
+use error::{to_pyerr, InterfaceError, ProgrammingError};
+use migrate::SqlxSchemaDiff;
+use model::{RegisteredModel, SqlxField, SqlxModelSchema};
+use params::{bind_batch, bind_params, bind_params_owned, QueryArgs};
 use str::unicode_from_str;
+use temporal::NaiveDatetime;
+use transaction::{SqlxTransaction, TxState};
 use typeref::NONE;
+use uuid::UuidFormat;
 
 struct PyTypeLut<T: Clone> {
     type_lut: dashmap::DashMap<*mut PyTypeObject, T>,
@@ -49,16 +85,22 @@ impl<T: Clone> PyTypeLut<T> {
         // Iter over type_lut to find a is_subclass match and store that type into the lut
         // For performance reasons we copy the info from the found type directly to the new type
         //  this means changing the associated type of a root-class will not overwrite derived classes (as they were effectively cached)
-        for kv in self.type_lut.iter() {
-            let stype = unsafe { PyType::from_borrowed_type_ptr(ptype.py(), kv.key().clone()) };
-            if ptype.is_subclass(&stype).unwrap() {
-                self.type_lut
-                    .insert(ptype.as_type_ptr(), kv.value().clone());
-                return Ok(kv.value().clone());
+        // NOTE: the iterator holds shard read-locks, so the insert must happen after it is dropped
+        let found = self.type_lut.iter().find_map(|kv| {
+            let stype = unsafe { PyType::from_borrowed_type_ptr(ptype.py(), *kv.key()) };
+            ptype
+                .is_subclass(&stype)
+                .unwrap_or(false)
+                .then(|| kv.value().clone())
+        });
+
+        match found {
+            Some(v) => {
+                self.type_lut.insert(ptype.as_type_ptr(), v.clone());
+                Ok(v)
             }
+            None => Err(()),
         }
-
-        Err(())
     }
 }
 
@@ -66,7 +108,23 @@ impl<T: Clone> PyTypeLut<T> {
 unsafe impl<T: Clone> Send for PyTypeLut<T> {}
 unsafe impl<T: Clone> Sync for PyTypeLut<T> {}
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    fn from_scheme(scheme: &str) -> Option<Self> {
+        match scheme {
+            "sqlite" => Some(Backend::Sqlite),
+            "postgres" | "postgresql" => Some(Backend::Postgres),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 enum TypeAffinity {
     Integer,
     Text,
@@ -75,6 +133,18 @@ enum TypeAffinity {
     Numeric,
 }
 
+impl TypeAffinity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TypeAffinity::Integer => "INTEGER",
+            TypeAffinity::Text => "TEXT",
+            TypeAffinity::Blob => "BLOB",
+            TypeAffinity::Real => "REAL",
+            TypeAffinity::Numeric => "NUMERIC",
+        }
+    }
+}
+
 #[derive(Clone)]
 struct SqlType {
     affinity: TypeAffinity,
@@ -83,188 +153,1087 @@ struct SqlType {
 
 static PY_TYPE_LUT: OnceLock<PyTypeLut<SqlType>> = OnceLock::new();
 
-struct TypeDef {
-    sql_type: &'static str, // Blob, varchar, etc
+fn duration_from_secs(name: &str, secs: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(secs).map_err(|_| {
+        PyValueError::new_err(format!("{name} must be a non-negative number of seconds"))
+    })
+}
+
+#[pyclass]
+struct SqlxDb {
+    conn: AnyPool,
+    codec: Arc<Codec>,
+    /// By qualname, behind a lock since async methods keep the pyclass borrowed while they run
+    registered_models: dashmap::DashMap<String, Arc<RegisteredModel>>,
 }
 
-fn try_get_root_sql_type<'py>(anno: &Bound<'py, PyAny>) -> Result<SqlType> {
-    let types_mod = anno.py().import(intern!(anno.py(), "types"))?;
-    let typing_mod = anno.py().import(intern!(anno.py(), "typing"))?;
+/// A raw result row, values are returned by storage class (see `value_to_ptr`)
+///
+/// The Any driver doesn't keep declared column types, so e.g. temporal columns come back as ISO 8601 text,
+/// UUIDs as their text (or 16 bytes on sqlite, see `uuid_format`) and decimals as their text.
+/// `SqlxDb.query` decodes rows into registered models, which convert them by field type.
+#[pyclass]
+struct SqlxRow(AnyRow);
 
-    let union_type = types_mod.getattr(intern!(anno.py(), "UnionType"))?;
-    let union_typing = typing_mod.getattr(intern!(anno.py(), "_UnionGenericAlias"))?;
+/// Converts a column value into a new python reference
+///
+/// Only storage classes are known here, e.g. temporal columns are returned as their ISO 8601 text;
+/// rows decoded into registered models convert them by field type.
+fn value_to_ptr<I>(row: &AnyRow, index: I) -> PyResult<*mut pyo3::ffi::PyObject>
+where
+    I: ColumnIndex<AnyRow> + std::fmt::Debug,
+{
+    let v = row.try_get_raw(&index).map_err(to_pyerr)?;
+    let v = ValueRef::to_owned(&v).to_owned();
+    let ptr = match v.kind {
+        sqlx::any::AnyValueKind::Bool(b) => unsafe { pyo3::ffi::PyBool_FromLong(b as _) },
+        sqlx::any::AnyValueKind::SmallInt(a) => unsafe { pyo3::ffi::PyLong_FromLongLong(a as _) },
+        sqlx::any::AnyValueKind::Integer(a) => unsafe { pyo3::ffi::PyLong_FromLongLong(a as _) },
+        sqlx::any::AnyValueKind::BigInt(a) => unsafe { pyo3::ffi::PyLong_FromLongLong(a) },
+        sqlx::any::AnyValueKind::Real(v) => unsafe { pyo3::ffi::PyFloat_FromDouble(v as f64) },
+        sqlx::any::AnyValueKind::Double(v) => unsafe { pyo3::ffi::PyFloat_FromDouble(v) },
+        sqlx::any::AnyValueKind::Text(v) => unicode_from_str(&v),
+        sqlx::any::AnyValueKind::Blob(v) => unsafe {
+            pyo3::ffi::PyBytes_FromStringAndSize(v.as_ptr() as *const _, v.len() as isize)
+        },
+        sqlx::any::AnyValueKind::Null(_) => use_immortal!(NONE),
+        _ => {
+            return Err(PyTypeError::new_err(format!(
+                "Unsupported value kind for column {index:?}"
+            )))
+        }
+    };
+    Ok(ptr)
+}
+
+/// Interned column names of `row`, to be shared by all rows of a result set
+fn column_names(py: Python<'_>, row: &AnyRow) -> Vec<Py<PyString>> {
+    row.columns()
+        .iter()
+        .map(|c| unsafe {
+            let mut ptr = unicode_from_str(c.name());
+            pyo3::ffi::PyUnicode_InternInPlace(&mut ptr);
+            Py::from_owned_ptr(py, ptr)
+        })
+        .collect()
+}
 
-    if let Ok(v) = anno.getattr(intern!(anno.py(), "__class__")) {
-        if v.is_exact_instance(&union_type) || v.is_exact_instance(&union_typing) {
-            let tuple_types = v.getattr(intern!(anno.py(), "__args__"))?;
-            todo!("if <type> | None, where <type> is primitive, use Nullable");
+fn row_to_tuple<'py>(py: Python<'py>, row: &AnyRow) -> PyResult<Bound<'py, PyTuple>> {
+    unsafe {
+        // SAFETY: unset items are NULL, which tuple dealloc handles in case of an error
+        let tuple = Bound::from_owned_ptr(py, pyo3::ffi::PyTuple_New(row.len() as isize))
+            .downcast_into_unchecked::<PyTuple>();
+        for i in 0..row.len() {
+            pyo3::ffi::PyTuple_SET_ITEM(tuple.as_ptr(), i as isize, value_to_ptr(row, i)?);
         }
+        Ok(tuple)
     }
-
-    Err(eyre::eyre!("No valid sqltype found for {:?}", anno))
 }
 
-// TODO: impl try_from<PyAny> (type annotation) for TypeDef
+fn row_to_dict<'py>(
+    py: Python<'py>,
+    row: &AnyRow,
+    names: &[Py<PyString>],
+) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    for (i, name) in names.iter().enumerate() {
+        let value = unsafe { Bound::from_owned_ptr(py, value_to_ptr(row, i)?) };
+        dict.set_item(name.bind(py), value)?;
+    }
+    Ok(dict)
+}
 
-struct RegisteredModel {
-    primary_key: String,
-    schema: HashMap<String, TypeDef>,
+#[derive(Clone, Copy)]
+enum RowFormat {
+    Tuple,
+    Dict,
 }
 
-#[pyclass]
-struct SqlxDb {
-    conn: AnyPool,
-    registered_models: HashMap<String, RegisteredModel>,
+impl RowFormat {
+    fn parse(format: &str) -> PyResult<Self> {
+        match format {
+            "tuple" => Ok(RowFormat::Tuple),
+            "dict" => Ok(RowFormat::Dict),
+            _ => Err(PyValueError::new_err(format!(
+                "row_format must be \"tuple\" or \"dict\", not {format:?}"
+            ))),
+        }
+    }
 }
 
-#[pyclass]
-struct SqlxRow(AnyRow);
+impl SqlxRow {
+    fn get_value<I>(&self, py: Python<'_>, index: I) -> PyResult<PyObject>
+    where
+        I: ColumnIndex<AnyRow> + std::fmt::Debug,
+    {
+        // SAFETY: `value_to_ptr` returns a new (or incref'd immortal) reference
+        Ok(unsafe { PyObject::from_owned_ptr(py, value_to_ptr(&self.0, index)?) })
+    }
+
+    /// Resolves a (possibly negative) python index into a column index
+    fn column_index(&self, index: isize) -> PyResult<usize> {
+        let len = self.0.len() as isize;
+        let idx = if index < 0 { index + len } else { index };
+        if !(0..len).contains(&idx) {
+            return Err(PyIndexError::new_err("SqlxRow index out of range"));
+        }
+        Ok(idx as usize)
+    }
 
+    fn values_vec(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        (0..self.0.len()).map(|i| self.get_value(py, i)).collect()
+    }
+}
+
+/// A result row, accessible both by column name (like a `Mapping`) and by position (like a `Sequence`)
+///
+/// The protocols are mixed: iterating a row yields its values (like a tuple), while `in` checks column
+/// names (like a dict), so `value in row` is not a membership test of the values. Use `keys()`/`items()`
+/// for the column names and `as_tuple()` to test values.
 #[pymethods]
 impl SqlxRow {
-    fn __getitem__<'py>(&self, key: Bound<'py, PyString>) -> *mut pyo3::ffi::PyObject {
-        // TODO key can technically be either string or int (for column index)
-        match self.0.try_get_raw(key.to_str().unwrap()) {
-            Ok(v) => {
-                let v = ValueRef::to_owned(&v).to_owned();
-                match v.kind {
-                    sqlx::any::AnyValueKind::Bool(b) => unsafe {
-                        pyo3::ffi::PyBool_FromLong(b as _)
-                    },
-                    sqlx::any::AnyValueKind::SmallInt(a) => unsafe {
-                        pyo3::ffi::PyLong_FromLongLong(a as _)
-                    },
-                    sqlx::any::AnyValueKind::Integer(a) => unsafe {
-                        pyo3::ffi::PyLong_FromLongLong(a as _)
-                    },
-                    sqlx::any::AnyValueKind::BigInt(a) => unsafe {
-                        pyo3::ffi::PyLong_FromLongLong(a)
-                    },
-                    sqlx::any::AnyValueKind::Real(v) => unsafe {
-                        pyo3::ffi::PyFloat_FromDouble(v as f64)
-                    },
-                    sqlx::any::AnyValueKind::Double(v) => unsafe {
-                        pyo3::ffi::PyFloat_FromDouble(v)
-                    },
-                    sqlx::any::AnyValueKind::Text(v) => unicode_from_str(&v),
-                    sqlx::any::AnyValueKind::Blob(v) => unsafe {
-                        pyo3::ffi::PyBytes_FromStringAndSize(
-                            v.as_ptr() as *const _,
-                            v.len() as isize,
-                        )
-                    },
-                    sqlx::any::AnyValueKind::Null(_) => use_immortal!(NONE),
-                    _ => todo!("Unknown value kind"),
-                }
+    fn __getitem__<'py>(&self, key: &Bound<'py, PyAny>) -> PyResult<PyObject> {
+        let py = key.py();
+        if let Ok(name) = key.downcast::<PyString>() {
+            self.get_value(py, name.to_str()?)
+        } else if let Ok(slice) = key.downcast::<PySlice>() {
+            let indices = slice.indices(self.0.len() as isize)?;
+            let values = (0..indices.slicelength)
+                .map(|i| self.get_value(py, (indices.start + i as isize * indices.step) as usize))
+                .collect::<PyResult<Vec<_>>>()?;
+            Ok(PyTuple::new(py, values)?.into_any().unbind())
+        } else if let Ok(index) = key.extract::<isize>() {
+            self.get_value(py, self.column_index(index)?)
+        } else {
+            Err(PyTypeError::new_err(format!(
+                "SqlxRow indices must be str, int or slice, not {}",
+                key.get_type().qualname()?
+            )))
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        PyTuple::new(py, self.values_vec(py)?)?.try_iter()
+    }
+
+    /// Whether `key` is a column name, false for anything but a str
+    fn __contains__(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        match key.downcast::<PyString>() {
+            Ok(name) => Ok(self.0.try_column(name.to_str()?).is_ok()),
+            Err(_) => Ok(false),
+        }
+    }
+
+    #[pyo3(signature = (key, default=None))]
+    fn get<'py>(&self, key: &Bound<'py, PyAny>, default: Option<PyObject>) -> PyResult<PyObject> {
+        match self.__getitem__(key) {
+            Ok(v) => Ok(v),
+            Err(e)
+                if e.is_instance_of::<PyKeyError>(key.py())
+                    || e.is_instance_of::<PyIndexError>(key.py()) =>
+            {
+                Ok(default.unwrap_or_else(|| key.py().None()))
             }
-            Err(_) => todo!(),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.columns().iter().map(|c| c.name()).collect()
+    }
+
+    fn values(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        self.values_vec(py)
+    }
+
+    fn items(&self, py: Python<'_>) -> PyResult<Vec<(&str, PyObject)>> {
+        Ok(self.keys().into_iter().zip(self.values_vec(py)?).collect())
+    }
+
+    /// All values in one pass, cheaper than indexing column by column
+    fn as_tuple<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyTuple>> {
+        row_to_tuple(py, &self.0)
+    }
+
+    fn as_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        row_to_dict(py, &self.0, &column_names(py, &self.0))
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let mut repr = String::from("<SqlxRow");
+        for (name, value) in self.items(py)? {
+            repr.push_str(&format!(" {name}={}", value.bind(py).repr()?));
         }
+        repr.push('>');
+        Ok(repr)
     }
 }
 
-#[pyclass]
-struct SqlxStreamRequest {
-    query: Pin<String>,
+struct ActiveStream {
+    // NOTE: fields drop in declaration order, the stream may borrow from `_conn` so it has to go first
+    stream: BoxStream<'static, Result<AnyRow, sqlx::Error>>,
+    // Locked transaction connection the stream was started on (if any), released once the stream is dropped
+    _conn: Option<OwnedMutexGuard<TxState>>,
+}
+
+struct RowStream {
+    // NOTE: fields drop in declaration order, the stream borrows from `_query` so it has to go first
     // TODO: mutex is a bit slow for something that isn't expected to be multi-threaded, maybe futex? (guard needs to be Send for py async)
-    stream: Option<Mutex<BoxStream<'static, Result<AnyRow, sqlx::Error>>>>,
+    active: Mutex<Option<ActiveStream>>,
+    _query: Pin<String>,
 }
 
-impl SqlxStreamRequest {
-    fn new(query: impl Into<String>, pool: &AnyPool) -> Self {
-        let mut me = Self {
-            query: Pin::new(query.into()),
-            stream: None,
-        };
-        me.run(pool);
-        me
+impl RowStream {
+    fn new(query: impl Into<String>, args: QueryArgs, pool: &AnyPool) -> Self {
+        Self::start(query, args, pool, None)
     }
 
-    fn run(&mut self, pool: &AnyPool) {
+    fn on_transaction(
+        query: impl Into<String>,
+        args: QueryArgs,
+        mut conn: OwnedMutexGuard<TxState>,
+    ) -> Self {
+        let tx = conn
+            .as_mut()
+            .expect("Transaction should be checked to be active");
+        // SAFETY: same lie as the query; the connection is owned by the locked transaction state,
+        //  and the guard is stored alongside (and dropped after) the stream
+        let executor = unsafe { &mut *(&mut **tx as *mut AnyConnection) };
+        Self::start(query, args, executor, Some(conn))
+    }
+
+    fn start<E>(
+        query: impl Into<String>,
+        args: QueryArgs,
+        executor: E,
+        conn: Option<OwnedMutexGuard<TxState>>,
+    ) -> Self
+    where
+        E: Executor<'static, Database = sqlx::Any>,
+    {
+        let query = Pin::new(query.into());
         // SAFETY: this is what we, in the business, call a "lie"; while the borrow lifetime is invalid the query should exists as long as the stream exists
-        //  Since Pin<String> should exists as long SqlStreamRequest exists
+        //  Since Pin<String> is stored alongside (and dropped after) the stream
         let query_str = unsafe {
-            std::str::from_utf8_unchecked(std::slice::from_raw_parts(
-                self.query.as_ptr(),
-                self.query.len(),
-            ))
+            std::str::from_utf8_unchecked(std::slice::from_raw_parts(query.as_ptr(), query.len()))
         };
         // Eqv to as_str().trustmybro() (unstable #![feature(str_as_str)])
         // let query_str: &'e str = unsafe { core::mem::transmute(self.query.as_str()) };
-        self.stream.replace(Mutex::new(pool.fetch(query_str)));
+        let stream = executor.fetch(sqlx::query_with(query_str, args));
+        Self {
+            active: Mutex::new(Some(ActiveStream {
+                stream,
+                _conn: conn,
+            })),
+            _query: query,
+        }
     }
+}
 
-    async fn next_row(&mut self) -> Result<AnyRow, sqlx::Error> {
-        let stream = self.stream.as_mut().expect("Stream was not initialized");
-        stream
-            .lock()
-            .await
-            .try_next()
-            .await?
-            .ok_or(sqlx::Error::RowNotFound)
+impl RowStream {
+    /// Whether the stream still holds its connection, an in-flight `take` counts as open
+    fn is_open(&self) -> bool {
+        self.active.try_lock().is_none_or(|v| v.is_some())
+    }
+
+    /// Drops the stream, waiting for an in-flight `take` to finish first
+    async fn close(&self) {
+        self.active.lock().await.take();
+    }
+
+    /// Pulls up to `n` rows, releasing the connection as soon as the stream is done
+    async fn take(&self, n: usize) -> Result<Vec<AnyRow>, sqlx::Error> {
+        let mut active = self.active.lock().await;
+        let mut rows = Vec::with_capacity(n);
+        while rows.len() < n {
+            let Some(stream) = active.as_mut() else {
+                break;
+            };
+            match stream.stream.try_next().await {
+                Ok(Some(row)) => rows.push(row),
+                Ok(None) => {
+                    active.take();
+                }
+                Err(e) => {
+                    active.take();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(rows)
     }
 }
 
-#[pymethods]
+/// `#[pymethods]` of a stream class with the async iterator and context manager protocols added
+///
+/// The class provides `next` (`None` once exhausted) and an inherent async `close_stream`, `_anext` yields
+/// the items of `next`.
+macro_rules! stream_pymethods {
+    (impl $ty:ident -> $item:ty { $($methods:tt)* }) => {
+        #[pymethods]
+        impl $ty {
+            $($methods)*
+
+            /// Drops the stream, which returns its connection to the pool (or unlocks its transaction)
+            ///
+            /// Waits for a pending `next` to finish first.
+            async fn close(&self) {
+                self.close_stream().await;
+            }
+
+            fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+                slf
+            }
+
+            // pyo3 doesn't support `async fn` for the `__anext__` slot, so it returns the coroutine of `_anext` instead
+            fn __anext__<'py>(slf: &Bound<'py, Self>) -> PyResult<Bound<'py, PyAny>> {
+                slf.call_method0(intern!(slf.py(), "_anext"))
+            }
+
+            #[pyo3(name = "_anext")]
+            async fn anext(&self) -> PyResult<$item> {
+                match self.next().await? {
+                    Some(item) => Ok(item),
+                    None => Err(PyStopAsyncIteration::new_err(())),
+                }
+            }
+
+            async fn __aenter__(slf: Py<Self>) -> Py<Self> {
+                slf
+            }
+
+            async fn __aexit__(
+                &self,
+                _exc_type: PyObject,
+                _exc_value: PyObject,
+                _traceback: PyObject,
+            ) -> bool {
+                self.close_stream().await;
+                false
+            }
+        }
+    };
+}
+
+#[pyclass]
+struct SqlxStreamRequest {
+    // Shared with in-flight `next_row` tasks, which may outlive the request if the python coroutine is dropped
+    inner: Arc<RowStream>,
+    closed: AtomicBool,
+    column_names: GILOnceCell<Vec<Py<PyString>>>,
+}
+
 impl SqlxStreamRequest {
-    async fn next(&mut self) -> Option<SqlxRow> {
-        let row = self.next_row().await.ok()?;
+    fn new(query: impl Into<String>, args: QueryArgs, pool: &AnyPool) -> Self {
+        Self {
+            inner: Arc::new(RowStream::new(query, args, pool)),
+            closed: AtomicBool::new(false),
+            column_names: GILOnceCell::new(),
+        }
+    }
+
+    fn on_transaction(
+        query: impl Into<String>,
+        args: QueryArgs,
+        conn: OwnedMutexGuard<TxState>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(RowStream::on_transaction(query, args, conn)),
+            closed: AtomicBool::new(false),
+            column_names: GILOnceCell::new(),
+        }
+    }
+
+    /// Returns up to `n` rows, fewer only once the stream is exhausted
+    async fn next_rows(&self, n: usize) -> PyResult<Vec<AnyRow>> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(InterfaceError::new_err("Stream was closed"));
+        }
+        let inner = self.inner.clone();
+        rt::spawn(async move { inner.take(n).await })
+            .await
+            .map_err(to_pyerr)
+    }
 
-        Some(SqlxRow(row))
+    /// Returns the next row, or `None` once the stream is exhausted
+    async fn next_row(&self) -> PyResult<Option<AnyRow>> {
+        Ok(self.next_rows(1).await?.pop())
+    }
+
+    /// Drops the stream, which returns its connection to the pool (or unlocks its transaction)
+    async fn close_stream(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        let inner = self.inner.clone();
+        rt::spawn(async move { inner.close().await }).await
+    }
+}
+
+stream_pymethods! {
+impl SqlxStreamRequest -> SqlxRow {
+    async fn next(&self) -> PyResult<Option<SqlxRow>> {
+        let row = self.next_row().await?;
+
+        Ok(row.map(SqlxRow))
         // TODO: convert row to Opaque PyObject
     }
+
+    /// Fetches up to `n` rows (fewer only at the end of the stream) converted to tuples or dicts in one pass
+    #[pyo3(signature = (n, row_format="tuple".to_owned()))]
+    async fn fetch_many(&self, n: usize, row_format: String) -> PyResult<Py<PyList>> {
+        let format = RowFormat::parse(&row_format)?;
+        let rows = self.next_rows(n).await?;
+
+        Python::with_gil(|py| {
+            let list = PyList::empty(py);
+            for row in rows.iter() {
+                match format {
+                    RowFormat::Tuple => list.append(row_to_tuple(py, row)?)?,
+                    RowFormat::Dict => {
+                        let names = self.column_names.get_or_init(py, || column_names(py, row));
+                        list.append(row_to_dict(py, row, names)?)?
+                    }
+                }
+            }
+            Ok(list.unbind())
+        })
+    }
+}
+}
+
+/// Stream of registered model instances, see `SqlxDb.query`
+#[pyclass]
+struct SqlxModelStream {
+    rows: SqlxStreamRequest,
+    model: Arc<RegisteredModel>,
+    decoder: GILOnceCell<ModelDecoder>,
+}
+
+impl SqlxModelStream {
+    fn decode(&self, py: Python<'_>, row: &AnyRow) -> PyResult<PyObject> {
+        let decoder = self
+            .decoder
+            .get_or_try_init(py, || ModelDecoder::new(py, self.model.clone(), row))?;
+        decoder.decode(py, row)
+    }
+
+    async fn close_stream(&self) {
+        self.rows.close_stream().await
+    }
+}
+
+stream_pymethods! {
+impl SqlxModelStream -> PyObject {
+    async fn next(&self) -> PyResult<Option<PyObject>> {
+        let row = self.rows.next_row().await?;
+        Python::with_gil(|py| row.map(|row| self.decode(py, &row)).transpose())
+    }
+
+    /// Fetches up to `n` instances, fewer only at the end of the stream
+    async fn fetch_many(&self, n: usize) -> PyResult<Vec<PyObject>> {
+        let rows = self.rows.next_rows(n).await?;
+        Python::with_gil(|py| rows.iter().map(|row| self.decode(py, row)).collect())
+    }
+}
+}
+
+/// Result of `execute`/`execute_many`
+///
+/// `last_insert_id` is sqlite's `last_insert_rowid()` after an `INSERT`/`REPLACE` that changed rows
+/// (`None` on postgres, use `RETURNING` instead). Like sqlite3's `lastrowid`, an upsert that took its
+/// `DO UPDATE` path reports the rowid of the connection's previous insert.
+#[pyclass(frozen)]
+struct SqlxQueryResult {
+    #[pyo3(get)]
+    rows_affected: u64,
+    #[pyo3(get)]
+    last_insert_id: Option<i64>,
+}
+
+#[pymethods]
+impl SqlxQueryResult {
+    fn __repr__(&self) -> String {
+        format!(
+            "SqlxQueryResult(rows_affected={}, last_insert_id={})",
+            self.rows_affected,
+            self.last_insert_id
+                .map_or_else(|| "None".to_owned(), |v| v.to_string())
+        )
+    }
+}
+
+impl From<AnyQueryResult> for SqlxQueryResult {
+    fn from(value: AnyQueryResult) -> Self {
+        SqlxQueryResult {
+            rows_affected: value.rows_affected(),
+            last_insert_id: value.last_insert_id(),
+        }
+    }
+}
+
+/// Whether `query` is an `INSERT`/`REPLACE`, possibly after a `WITH` clause
+fn is_insert(query: &str) -> bool {
+    let mut words = query
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|v| !v.is_empty());
+    let is_insert_kw =
+        |v: &str| v.eq_ignore_ascii_case("insert") || v.eq_ignore_ascii_case("replace");
+    match words.next() {
+        Some(v) if v.eq_ignore_ascii_case("with") => words.any(is_insert_kw),
+        Some(v) => is_insert_kw(v),
+        None => false,
+    }
+}
+
+impl SqlxQueryResult {
+    async fn execute(
+        conn: &mut AnyConnection,
+        backend: Backend,
+        query: &str,
+        args: QueryArgs,
+    ) -> Result<Self, sqlx::Error> {
+        Self::from(conn.execute(sqlx::query_with(query, args)).await?)
+            .with_last_insert_id(conn, backend, query)
+            .await
+    }
+
+    /// Executes every query with its arguments (see `bind_batch`) on the same connection
+    ///
+    /// `rows_affected` is summed, `last_insert_id` is that of the final execution.
+    async fn execute_batch(
+        conn: &mut AnyConnection,
+        backend: Backend,
+        batch: Vec<(String, QueryArgs)>,
+    ) -> Result<Self, sqlx::Error> {
+        let mut result = SqlxQueryResult {
+            rows_affected: 0,
+            last_insert_id: None,
+        };
+        let mut last_query = None;
+        for (query, args) in batch {
+            result.merge(Self::from(
+                conn.execute(sqlx::query_with(&query, args)).await?,
+            ));
+            last_query = Some(query);
+        }
+        match last_query {
+            Some(query) => result.with_last_insert_id(conn, backend, &query).await,
+            None => Ok(result),
+        }
+    }
+
+    /// Sets sqlite's `last_insert_rowid()` after an insert that changed rows
+    ///
+    /// The Any driver doesn't forward sqlite's rowid, so it's queried from the same connection (like sqlite3's `lastrowid`).
+    /// Batches only query it once, after their final execution.
+    async fn with_last_insert_id(
+        mut self,
+        conn: &mut AnyConnection,
+        backend: Backend,
+        query: &str,
+    ) -> Result<Self, sqlx::Error> {
+        if backend == Backend::Sqlite
+            && self.last_insert_id.is_none()
+            && self.rows_affected > 0
+            && is_insert(query)
+        {
+            self.last_insert_id = Some(
+                sqlx::query_scalar("select last_insert_rowid()")
+                    .fetch_one(&mut *conn)
+                    .await?,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Accumulates the result of a following execution
+    fn merge(&mut self, other: Self) {
+        self.rows_affected += other.rows_affected;
+        self.last_insert_id = other.last_insert_id;
+    }
+}
+
+impl SqlxDb {
+    async fn execute_statement(&self, query: String, args: QueryArgs) -> PyResult<SqlxQueryResult> {
+        let (pool, backend) = (self.conn.clone(), self.codec.backend);
+        rt::spawn(async move {
+            let mut conn = pool.acquire().await?;
+            SqlxQueryResult::execute(&mut conn, backend, &query, args).await
+        })
+        .await
+        .map_err(to_pyerr)
+    }
+
+    /// Executes unparameterized statements in a single transaction
+    async fn execute_script(&self, statements: Vec<String>) -> PyResult<()> {
+        let pool = self.conn.clone();
+        rt::spawn(async move {
+            let mut tx = pool.begin().await?;
+            for statement in statements.iter() {
+                tx.execute(statement.as_str()).await?;
+            }
+            tx.commit().await
+        })
+        .await
+        .map_err(to_pyerr)
+    }
+
+    async fn schema_diff(&self, model: &RegisteredModel) -> PyResult<SqlxSchemaDiff> {
+        let (pool, backend) = (self.conn.clone(), self.codec.backend);
+        let live = rt::spawn(migrate::introspect(pool, backend, model.table_name.clone()))
+            .await
+            .map_err(to_pyerr)?;
+        Ok(Python::with_gil(|py| {
+            SqlxSchemaDiff::new(py, model, backend, live)
+        }))
+    }
+
+    /// Looks up the registration of a model class
+    fn registered(&self, model: &Bound<'_, PyAny>) -> PyResult<Arc<RegisteredModel>> {
+        let model = model
+            .downcast::<PyType>()
+            .map_err(|_| PyTypeError::new_err("Expected a model class"))?;
+        let qualname = model.qualname()?;
+
+        let registered = self
+            .registered_models
+            .get(qualname.to_str()?)
+            .map(|v| v.clone());
+        match registered {
+            Some(v) if v.model.bind(model.py()).is(model) => Ok(v),
+            Some(_) => Err(ProgrammingError::new_err(format!(
+                "A different model named {qualname} was registered"
+            ))),
+            None => Err(ProgrammingError::new_err(format!(
+                "Model {qualname} is not registered, call `register_model` first"
+            ))),
+        }
+    }
 }
 
 #[pymethods]
 impl SqlxDb {
+    /// Creates a connection pool for `connection_str`
+    ///
+    /// Timeouts are in seconds, unset options use the sqlx defaults.
+    /// With `eager=True` the first connection is established (and errors raised) here instead of on first use.
+    /// `blob_encoding` (`"json"`, `"msgpack"` or `(encode, decode)` callables) encodes dict/list parameters and
+    /// model fields without an `encoding` of their own.
+    /// `naive_datetime` (`"reject"`, `"utc"`, `"local"` or a `timezone`/`ZoneInfo`) is the timezone assumed for
+    /// datetimes without one, both when writing and reading.
+    /// `uuid_format` (`"bytes"` or `"text"`) is how sqlite stores UUIDs, postgres uses its native `uuid`.
+    ///
+    /// JSON, temporal, UUID and decimal values are stored natively on postgres (`jsonb`, `timestamptz`, ...), which
+    /// the driver only exchanges as text: temporal, UUID and decimal parameters are cast automatically
+    /// (`$1::TIMESTAMPTZ`), JSON ones need a cast (`$1::jsonb`), and raw queries need to select such columns `::text`.
     #[new]
-    fn new(connection_str: &str) -> Self {
-        let pool_options =
-            AnyConnectOptions::from_str(connection_str).expect("Failed to parse connection string");
+    #[pyo3(signature = (
+        connection_str,
+        *,
+        max_connections=None,
+        min_connections=None,
+        acquire_timeout=None,
+        idle_timeout=None,
+        max_lifetime=None,
+        test_before_acquire=None,
+        eager=false,
+        blob_encoding=None,
+        naive_datetime=None,
+        uuid_format="bytes",
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python<'_>,
+        connection_str: &str,
+        max_connections: Option<u32>,
+        min_connections: Option<u32>,
+        acquire_timeout: Option<f64>,
+        idle_timeout: Option<f64>,
+        max_lifetime: Option<f64>,
+        test_before_acquire: Option<bool>,
+        eager: bool,
+        blob_encoding: Option<Bound<'_, PyAny>>,
+        naive_datetime: Option<Bound<'_, PyAny>>,
+        uuid_format: &str,
+    ) -> PyResult<Self> {
+        let connect_options = AnyConnectOptions::from_str(connection_str).map_err(to_pyerr)?;
+        let scheme = connect_options.database_url.scheme();
+        let backend = Backend::from_scheme(scheme).ok_or_else(|| {
+            InterfaceError::new_err(format!("Unsupported database backend {scheme:?}"))
+        })?;
+        let blob_encoding = match blob_encoding {
+            Some(v) => BlobEncoding::from_py(&v)?,
+            None => BlobEncoding::json(py)?,
+        };
+        let naive_datetime = match naive_datetime {
+            Some(v) => NaiveDatetime::from_py(&v)?,
+            None => NaiveDatetime::Utc,
+        };
+        let codec = Arc::new(Codec {
+            backend,
+            blob_encoding: Arc::new(blob_encoding),
+            naive_datetime,
+            uuid_format: UuidFormat::from_name(uuid_format)?,
+        });
 
-        // TODO(perf): share the type_lut to be global lut (reason for dashmap)
-        SqlxDb {
-            conn: AnyPool::connect_lazy_with(pool_options),
-            registered_models: HashMap::new(),
+        let mut pool_options = AnyPoolOptions::new();
+        if let Some(v) = max_connections {
+            pool_options = pool_options.max_connections(v);
+        }
+        if let Some(v) = min_connections {
+            pool_options = pool_options.min_connections(v);
+        }
+        if let Some(v) = acquire_timeout {
+            pool_options = pool_options.acquire_timeout(duration_from_secs("acquire_timeout", v)?);
         }
+        if let Some(v) = idle_timeout {
+            pool_options = pool_options.idle_timeout(duration_from_secs("idle_timeout", v)?);
+        }
+        if let Some(v) = max_lifetime {
+            pool_options = pool_options.max_lifetime(duration_from_secs("max_lifetime", v)?);
+        }
+        if let Some(v) = test_before_acquire {
+            pool_options = pool_options.test_before_acquire(v);
+        }
+
+        let conn = if eager {
+            py.allow_threads(|| {
+                async_std::task::block_on(pool_options.connect_with(connect_options))
+            })
+            .map_err(to_pyerr)?
+        } else {
+            pool_options.connect_lazy_with(connect_options)
+        };
+
+        // TODO(perf): share the type_lut to be global lut (reason for dashmap)
+        Ok(SqlxDb {
+            conn,
+            codec,
+            registered_models: dashmap::DashMap::new(),
+        })
     }
 
-    fn start_query<'py>(&mut self, query: Bound<'py, PyAny>) -> PyResult<SqlxStreamRequest> {
+    #[pyo3(signature = (query, params=None))]
+    fn start_query<'py>(
+        &self,
+        query: Bound<'py, PyAny>,
+        params: Option<Bound<'py, PyAny>>,
+    ) -> PyResult<SqlxStreamRequest> {
         let query = query.downcast_into_exact::<PyString>()?;
-        let query = query.to_str()?;
-        let req = SqlxStreamRequest::new(query, &self.conn);
+        let (query, args) = bind_params(query.to_str()?, params.as_ref(), &self.codec)?;
+        let req = SqlxStreamRequest::new(query, args, &self.conn);
 
         Ok(req)
     }
 
-    fn register_model<'py>(&mut self, model: &Bound<'py, PyAny>) -> PyResult<bool> {
-        let annotations = model
-            .getattr(intern!(model.py(), "__annotations__"))
-            .expect("Expected model type to have fields accessible in `__annotations__` (msgspec or pydantic)");
-        let annotations: &Bound<'py, PyDict> = annotations.downcast()?;
-
-        for (k, v) in annotations.iter() {
-            todo!("Get PyType of various desired types/typings (e.g. UnionType, GenericAlias, primitives) by PyType::as_type_ptr into a HashMap to know how to handle them");
-
-            println!("k={:?} v={:?}", k, v);
-            let type_name = v
-                .downcast::<PyType>()
-                .expect("Not type")
-                .qualname()
-                .unwrap()
-                .to_string();
-            match type_name.as_str() {
-                "int" => println!("{k} is an Integer"),
-                "bool" => todo!("bool definition"),
-                "float" => todo!("float definition"),
-                "str" => todo!("str definition"),
-                "dict" => todo!("dict definition"),
-                "bytes" => todo!("Bytes definition"),
-                "Annotated" => todo!("Recurse with options"),
-                _ => unimplemented!("{type_name:?} not added yet"),
-            }
-            // todo!("Read type metadata")
+    /// Starts a transaction, `isolation_level` (e.g. "repeatable read") is only supported on postgres
+    #[pyo3(signature = (isolation_level=None))]
+    async fn begin(&self, isolation_level: Option<String>) -> PyResult<SqlxTransaction> {
+        SqlxTransaction::start(self.conn.clone(), self.codec.clone(), isolation_level).await
+    }
+
+    #[pyo3(signature = (query, params=None))]
+    async fn execute(&self, query: String, params: Option<PyObject>) -> PyResult<SqlxQueryResult> {
+        let (query, args) = bind_params_owned(&query, params, &self.codec)?;
+        self.execute_statement(query, args).await
+    }
+
+    /// Executes `query` once for every parameter set in `seq_of_params`
+    ///
+    /// All executions share one connection (and thus its cached prepared statements) inside a transaction,
+    /// so either every parameter set is applied or none are.
+    async fn execute_many(
+        &self,
+        query: String,
+        seq_of_params: PyObject,
+    ) -> PyResult<SqlxQueryResult> {
+        let batch = bind_batch(&query, seq_of_params, &self.codec)?;
+        let (pool, backend) = (self.conn.clone(), self.codec.backend);
+
+        rt::spawn(async move {
+            let mut tx = pool.begin().await?;
+            let result = SqlxQueryResult::execute_batch(&mut tx, backend, batch).await?;
+            tx.commit().await?;
+            Ok(result)
+        })
+        .await
+        .map_err(to_pyerr)
+    }
+
+    #[pyo3(signature = (query, params=None))]
+    async fn fetch_one(&self, query: String, params: Option<PyObject>) -> PyResult<SqlxRow> {
+        let (query, args) = bind_params_owned(&query, params, &self.codec)?;
+        let pool = self.conn.clone();
+
+        let row = rt::spawn(async move { pool.fetch_one(sqlx::query_with(&query, args)).await })
+            .await
+            .map_err(to_pyerr)?;
+        Ok(SqlxRow(row))
+    }
+
+    #[pyo3(signature = (query, params=None))]
+    async fn fetch_optional(
+        &self,
+        query: String,
+        params: Option<PyObject>,
+    ) -> PyResult<Option<SqlxRow>> {
+        let (query, args) = bind_params_owned(&query, params, &self.codec)?;
+        let pool = self.conn.clone();
+
+        let row =
+            rt::spawn(async move { pool.fetch_optional(sqlx::query_with(&query, args)).await })
+                .await
+                .map_err(to_pyerr)?;
+        Ok(row.map(SqlxRow))
+    }
+
+    #[pyo3(signature = (query, params=None))]
+    async fn fetch_all(&self, query: String, params: Option<PyObject>) -> PyResult<Vec<SqlxRow>> {
+        let (query, args) = bind_params_owned(&query, params, &self.codec)?;
+        let pool = self.conn.clone();
+
+        let rows = rt::spawn(async move { pool.fetch_all(sqlx::query_with(&query, args)).await })
+            .await
+            .map_err(to_pyerr)?;
+        Ok(rows.into_iter().map(SqlxRow).collect())
+    }
+
+    /// Reflects a model class and registers it by its qualname, replacing any previous registration
+    fn register_model<'py>(&self, model: &Bound<'py, PyAny>) -> PyResult<SqlxModelSchema> {
+        let model = model
+            .downcast::<PyType>()
+            .map_err(|_| PyTypeError::new_err("Expected a model class"))?;
+        let registered = Arc::new(RegisteredModel::from_model(model, &self.codec)?);
+        self.registered_models
+            .insert(model.qualname()?.to_string(), registered.clone());
+
+        Ok(SqlxModelSchema(registered))
+    }
+
+    /// Like `start_query`, but streams instances of a registered model
+    ///
+    /// Columns are matched to fields by their column name, missing columns are left to the model's defaults.
+    /// On postgres, the query of a model with JSON, temporal, UUID, decimal or enum fields is wrapped to cast
+    /// their columns to text, so it has to return every column of the model.
+    #[pyo3(signature = (model, query, params=None))]
+    fn query<'py>(
+        &self,
+        model: &Bound<'py, PyAny>,
+        query: Bound<'py, PyAny>,
+        params: Option<Bound<'py, PyAny>>,
+    ) -> PyResult<SqlxModelStream> {
+        let model = self.registered(model)?;
+        let query = query.downcast_into_exact::<PyString>()?;
+        let (query, args) = bind_params(query.to_str()?, params.as_ref(), &self.codec)?;
+        let query = crud::model_query(&model, self.codec.backend, query);
+
+        Ok(SqlxModelStream {
+            rows: SqlxStreamRequest::new(query, args, &self.conn),
+            model,
+            decoder: GILOnceCell::new(),
+        })
+    }
+
+    /// `CREATE TABLE` and `CREATE INDEX` statements of a registered model
+    #[pyo3(signature = (model, if_not_exists=true))]
+    fn ddl(&self, model: &Bound<'_, PyAny>, if_not_exists: bool) -> PyResult<Vec<String>> {
+        let registered = self.registered(model)?;
+        Ok(ddl::create_table(
+            model.py(),
+            &registered,
+            self.codec.backend,
+            if_not_exists,
+        ))
+    }
+
+    /// Creates the table and indexes of a registered model in a single transaction
+    #[pyo3(signature = (model, if_not_exists=true))]
+    async fn create_table(&self, model: PyObject, if_not_exists: bool) -> PyResult<()> {
+        let statements = Python::with_gil(|py| self.ddl(model.bind(py), if_not_exists))?;
+        self.execute_script(statements).await
+    }
+
+    /// Compares the table of a registered model with the model's schema
+    async fn diff_schema(&self, model: PyObject) -> PyResult<SqlxSchemaDiff> {
+        let registered = Python::with_gil(|py| self.registered(model.bind(py)))?;
+        self.schema_diff(&registered).await
+    }
+
+    /// Alters (or creates) the table of a registered model to match the model
+    ///
+    /// Only applies changes that keep all data unless `allow_destructive`, see `diff_schema`
+    /// for what is left to migrate. Returns the executed statements.
+    #[pyo3(signature = (model, allow_destructive=false))]
+    async fn migrate(&self, model: PyObject, allow_destructive: bool) -> PyResult<Vec<String>> {
+        let registered = Python::with_gil(|py| self.registered(model.bind(py)))?;
+        let diff = self.schema_diff(&registered).await?;
+        let statements = Python::with_gil(|py| {
+            migrate::migration_statements(
+                py,
+                &registered,
+                self.codec.backend,
+                &diff,
+                allow_destructive,
+            )
+        })?;
+
+        self.execute_script(statements.clone()).await?;
+        Ok(statements)
+    }
+
+    /// Applies the pending `NNNN_name.sql` files of the directory `path` in order of their version
+    ///
+    /// Applied versions and checksums are recorded in the `_pysqlx_migrations` table.
+    /// Returns the names of the applied migrations, or only lists the pending ones when `dry_run`.
+    #[pyo3(signature = (path, *, dry_run=false))]
+    async fn run_migrations(&self, path: PathBuf, dry_run: bool) -> PyResult<Vec<String>> {
+        let files = Python::with_gil(|py| migrate::read_migrations(py, &path))?;
+        let (pool, backend) = (self.conn.clone(), self.codec.backend);
+        rt::spawn(migrate::run_migrations(pool, backend, files, dry_run)).await
+    }
+
+    /// Inserts an instance of a registered model
+    ///
+    /// Returns the (generated) primary key when `returning`, the `SqlxQueryResult` otherwise.
+    #[pyo3(signature = (instance, *, returning=false))]
+    async fn insert(&self, instance: PyObject, returning: bool) -> PyResult<PyObject> {
+        let (query, args) = Python::with_gil(|py| {
+            let instance = instance.bind(py);
+            let registered = self.registered(&instance.get_type())?;
+            let mut statements = crud::insert_statements(
+                &registered,
+                &self.codec,
+                std::slice::from_ref(instance),
+                returning,
+            )?;
+            Ok::<_, PyErr>(statements.remove(0))
+        })?;
+
+        if returning {
+            let pool = self.conn.clone();
+            let row =
+                rt::spawn(async move { pool.fetch_one(sqlx::query_with(&query, args)).await })
+                    .await
+                    .map_err(to_pyerr)?;
+            return Python::with_gil(|py| SqlxRow(row).get_value(py, 0));
         }
-        todo!("Reflect model to extract types")
+
+        let result = self.execute_statement(query, args).await?;
+        Python::with_gil(|py| Ok(Py::new(py, result)?.into_any()))
+    }
+
+    /// Inserts instances of a registered model in a single transaction, using multi-row `INSERT`s
+    ///
+    /// Returns a list of the (generated) primary keys when `returning`, the `SqlxQueryResult` otherwise.
+    #[pyo3(signature = (instances, *, returning=false))]
+    async fn insert_many(&self, instances: PyObject, returning: bool) -> PyResult<PyObject> {
+        let statements = Python::with_gil(|py| {
+            let instances = instances
+                .bind(py)
+                .try_iter()?
+                .collect::<PyResult<Vec<_>>>()?;
+            let Some(first) = instances.first() else {
+                return Ok(Vec::new());
+            };
+            let registered = self.registered(&first.get_type())?;
+            crud::insert_statements(&registered, &self.codec, &instances, returning)
+        })?;
+        let (pool, backend) = (self.conn.clone(), self.codec.backend);
+
+        let (result, rows) = rt::spawn(async move {
+            let mut result = SqlxQueryResult {
+                rows_affected: 0,
+                last_insert_id: None,
+            };
+            let mut rows = Vec::new();
+
+            let mut tx = pool.begin().await?;
+            let mut last_query = None;
+            for (query, args) in statements {
+                if returning {
+                    rows.extend(tx.fetch_all(sqlx::query_with(&query, args)).await?);
+                } else {
+                    result.merge(SqlxQueryResult::from(
+                        tx.execute(sqlx::query_with(&query, args)).await?,
+                    ));
+                    last_query = Some(query);
+                }
+            }
+            if let Some(query) = last_query {
+                result = result.with_last_insert_id(&mut tx, backend, &query).await?;
+            }
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>((result, rows))
+        })
+        .await
+        .map_err(to_pyerr)?;
+
+        Python::with_gil(|py| {
+            if !returning {
+                return Ok(Py::new(py, result)?.into_any());
+            }
+            let pks = rows
+                .into_iter()
+                .map(|row| SqlxRow(row).get_value(py, 0))
+                .collect::<PyResult<Vec<_>>>()?;
+            Ok(PyList::new(py, pks)?.into_any().unbind())
+        })
+    }
+
+    /// Fetches the instance of a registered model with primary key `pk`, `None` if there is none
+    async fn get(&self, model: PyObject, pk: PyObject) -> PyResult<Option<PyObject>> {
+        let (registered, query, args) = Python::with_gil(|py| {
+            let registered = self.registered(model.bind(py))?;
+            let (query, args) = crud::select_statement(&registered, &self.codec, pk.bind(py))?;
+            Ok::<_, PyErr>((registered, query, args))
+        })?;
+        let pool = self.conn.clone();
+
+        let row =
+            rt::spawn(async move { pool.fetch_optional(sqlx::query_with(&query, args)).await })
+                .await
+                .map_err(to_pyerr)?;
+        Python::with_gil(|py| {
+            row.map(|row| ModelDecoder::new(py, registered, &row)?.decode(py, &row))
+                .transpose()
+        })
+    }
+
+    /// Updates the row of an instance of a registered model, only the named `fields` if given
+    #[pyo3(signature = (instance, fields=None))]
+    async fn update(
+        &self,
+        instance: PyObject,
+        fields: Option<Vec<String>>,
+    ) -> PyResult<SqlxQueryResult> {
+        let (query, args) = Python::with_gil(|py| {
+            let instance = instance.bind(py);
+            let registered = self.registered(&instance.get_type())?;
+            crud::update_statement(&registered, &self.codec, instance, fields)
+        })?;
+        self.execute_statement(query, args).await
+    }
+
+    /// Deletes the row of a registered model with primary key `pk`
+    async fn delete(&self, model: PyObject, pk: PyObject) -> PyResult<SqlxQueryResult> {
+        let (query, args) = Python::with_gil(|py| {
+            let registered = self.registered(model.bind(py))?;
+            crud::delete_statement(&registered, &self.codec, pk.bind(py))
+        })?;
+        self.execute_statement(query, args).await
+    }
+
+    /// Inserts an instance of a registered model, or updates its row if the primary key exists
+    async fn upsert(&self, instance: PyObject) -> PyResult<SqlxQueryResult> {
+        let (query, args) = Python::with_gil(|py| {
+            let instance = instance.bind(py);
+            let registered = self.registered(&instance.get_type())?;
+            crud::upsert_statement(&registered, &self.codec, instance)
+        })?;
+        self.execute_statement(query, args).await
     }
 }
 
@@ -272,7 +1241,9 @@ impl SqlxDb {
 #[pymodule]
 fn pysqlx(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     sqlx::any::install_default_drivers();
-    typeref::init_typerefs();
+    if !typeref::init_typerefs() {
+        return Err(PyErr::fetch(py));
+    }
 
     let lut = PY_TYPE_LUT.get_or_init(|| PyTypeLut::new());
 
@@ -283,15 +1254,28 @@ fn pysqlx(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
             nullable: false,
         },
     );
-
     lut.add_type_explicit(
-        PyInt::type_object(py),
+        PyBool::type_object(py),
         SqlType {
             affinity: TypeAffinity::Integer,
             nullable: false,
         },
     );
-    // Dicts will be encoded with msgspack or similar (todo: specify encoding method?)
+    lut.add_type_explicit(
+        PyFloat::type_object(py),
+        SqlType {
+            affinity: TypeAffinity::Real,
+            nullable: false,
+        },
+    );
+    lut.add_type_explicit(
+        PyString::type_object(py),
+        SqlType {
+            affinity: TypeAffinity::Text,
+            nullable: false,
+        },
+    );
+    // Dicts and lists are encoded with the `BlobEncoding` of the database or field
     lut.add_type_explicit(
         PyDict::type_object(py),
         SqlType {
@@ -299,6 +1283,42 @@ fn pysqlx(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
             nullable: false,
         },
     );
+    lut.add_type_explicit(
+        PyList::type_object(py),
+        SqlType {
+            affinity: TypeAffinity::Blob,
+            nullable: false,
+        },
+    );
+    // Temporal types are stored as ISO 8601 text on sqlite (see `temporal`)
+    for ptype in [
+        PyDateTime::type_object(py),
+        PyDate::type_object(py),
+        PyTime::type_object(py),
+    ] {
+        lut.add_type_explicit(
+            ptype,
+            SqlType {
+                affinity: TypeAffinity::Text,
+                nullable: false,
+            },
+        );
+    }
+    lut.add_type_explicit(
+        decimal::decimal_type(py),
+        SqlType {
+            affinity: TypeAffinity::Numeric,
+            nullable: false,
+        },
+    );
+    // UUIDs are bound as text or bytes depending on `Codec::uuid_affinity`
+    lut.add_type_explicit(
+        uuid::uuid_type(py),
+        SqlType {
+            affinity: TypeAffinity::Text,
+            nullable: false,
+        },
+    );
     lut.add_type_explicit(
         PyBytes::type_object(py),
         SqlType {
@@ -307,9 +1327,38 @@ fn pysqlx(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
         },
     );
 
+    py.import(intern!(py, "atexit"))?.call_method1(
+        intern!(py, "register"),
+        (wrap_pyfunction!(rt::wait_for_wakers, m)?,),
+    )?;
+
+    error::add_exceptions(m)?;
     m.add_class::<SqlxDb>()?;
     m.add_class::<SqlxRow>()?;
     m.add_class::<SqlxStreamRequest>()?;
+    m.add_class::<SqlxModelStream>()?;
+    m.add_class::<SqlxQueryResult>()?;
+    m.add_class::<SqlxTransaction>()?;
+    m.add_class::<SqlxModelSchema>()?;
+    m.add_class::<SqlxField>()?;
+    m.add_class::<SqlxSchemaDiff>()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_insert_statements() {
+        assert!(is_insert("INSERT INTO t VALUES (1)"));
+        assert!(is_insert("  replace into t values (1)"));
+        assert!(is_insert(
+            "WITH v(a) AS (SELECT 1) INSERT INTO t SELECT a FROM v"
+        ));
+        assert!(!is_insert("WITH v(a) AS (SELECT 1) SELECT a FROM v"));
+        assert!(!is_insert("UPDATE t SET inserted = 1"));
+        assert!(!is_insert(""));
+    }
+}
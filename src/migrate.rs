@@ -0,0 +1,668 @@
+use std::path::Path;
+
+use pyo3::{exceptions::PyValueError, intern, prelude::*, types::PyBytes};
+use sqlx::{AnyPool, Executor};
+
+use crate::{
+    ddl::{
+        add_enum_values, column_def, column_type, create_enum_type, create_index, create_table,
+        create_table_as, index_name, indexed_fields, quote_ident,
+    },
+    enums::Choice,
+    error::{to_pyerr, to_pyerr_context, ProgrammingError},
+    model::{FieldDef, RegisteredModel},
+    params::placeholder,
+    Backend,
+};
+
+/// Column of an existing table
+struct LiveColumn {
+    name: String,
+    /// Declared type, may be empty on sqlite
+    sql_type: String,
+    nullable: bool,
+    /// Labels of a native postgres enum column, in order
+    enum_labels: Vec<String>,
+}
+
+/// Columns and index names of an existing table
+pub(crate) struct LiveTable {
+    columns: Vec<LiveColumn>,
+    indexes: Vec<String>,
+    has_rows: bool,
+}
+
+/// Reads the columns and indexes of `table`, `None` if it doesn't exist
+pub(crate) async fn introspect(
+    pool: AnyPool,
+    backend: Backend,
+    table: String,
+) -> Result<Option<LiveTable>, sqlx::Error> {
+    let (columns_sql, indexes_sql) = match backend {
+        Backend::Sqlite => (
+            "SELECT name, type, \"notnull\" FROM pragma_table_info(?)",
+            "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = ?",
+        ),
+        // information_schema uses domain types, which the Any driver can't decode without a cast.
+        // Enums are reported as USER-DEFINED, their name is the `udt_name`.
+        Backend::Postgres => (
+            "SELECT column_name::text, \
+             (CASE WHEN data_type = 'USER-DEFINED' THEN udt_name ELSE data_type END)::text, \
+             (CASE WHEN is_nullable = 'NO' THEN 1 ELSE 0 END)::int8 \
+             FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1 \
+             ORDER BY ordinal_position",
+            "SELECT indexname::text FROM pg_indexes WHERE schemaname = current_schema() AND tablename = $1",
+        ),
+    };
+
+    let columns: Vec<(String, String, i64)> = sqlx::query_as(columns_sql)
+        .bind(table.clone())
+        .fetch_all(&pool)
+        .await?;
+    if columns.is_empty() {
+        return Ok(None);
+    }
+    let indexes: Vec<(String,)> = sqlx::query_as(indexes_sql)
+        .bind(table.clone())
+        .fetch_all(&pool)
+        .await?;
+    let rows_sql = format!("SELECT 1 FROM {} LIMIT 1", quote_ident(&table));
+    let has_rows = sqlx::query(&rows_sql)
+        .fetch_optional(&pool)
+        .await?
+        .is_some();
+    let enum_labels: Vec<(String, String)> =
+        match backend {
+            Backend::Sqlite => Vec::new(),
+            Backend::Postgres => sqlx::query_as(
+                "SELECT c.column_name::text, e.enumlabel::text FROM information_schema.columns c \
+                 JOIN pg_namespace n ON n.nspname = c.udt_schema \
+                 JOIN pg_type t ON t.typnamespace = n.oid AND t.typname = c.udt_name \
+                 JOIN pg_enum e ON e.enumtypid = t.oid \
+                 WHERE c.table_schema = current_schema() AND c.table_name = $1 \
+                 ORDER BY c.ordinal_position, e.enumsortorder",
+            )
+            .bind(table)
+            .fetch_all(&pool)
+            .await?,
+        };
+
+    Ok(Some(LiveTable {
+        columns: columns
+            .into_iter()
+            .map(|(name, sql_type, not_null)| LiveColumn {
+                enum_labels: enum_labels
+                    .iter()
+                    .filter(|(column, _)| *column == name)
+                    .map(|(_, label)| label.clone())
+                    .collect(),
+                name,
+                sql_type,
+                nullable: not_null == 0,
+            })
+            .collect(),
+        indexes: indexes.into_iter().map(|(name,)| name).collect(),
+        has_rows,
+    }))
+}
+
+/// Type affinity of a declared sqlite column type (https://www.sqlite.org/datatype3.html#determination_of_column_affinity)
+fn sqlite_affinity(declared: &str) -> &'static str {
+    let declared = declared.to_ascii_uppercase();
+    if declared.contains("INT") {
+        "INTEGER"
+    } else if ["CHAR", "CLOB", "TEXT"]
+        .iter()
+        .any(|v| declared.contains(v))
+    {
+        "TEXT"
+    } else if declared.contains("BLOB") || declared.is_empty() {
+        "BLOB"
+    } else if ["REAL", "FLOA", "DOUB"]
+        .iter()
+        .any(|v| declared.contains(v))
+    {
+        "REAL"
+    } else {
+        "NUMERIC"
+    }
+}
+
+/// Postgres type name as reported by `information_schema.columns.data_type`
+fn normalize_pg_type(sql_type: &str) -> String {
+    // Enum names are quoted identifiers in the DDL
+    let sql_type = sql_type.replace('"', "").to_ascii_lowercase();
+    // Precision/length modifiers aren't part of `data_type`
+    let sql_type = match sql_type.find('(') {
+        Some(idx) => sql_type[..idx].trim_end(),
+        None => sql_type.as_str(),
+    };
+    match sql_type {
+        "int8" => "bigint",
+        "int" | "int4" => "integer",
+        "int2" => "smallint",
+        "float8" => "double precision",
+        "float4" => "real",
+        "bool" => "boolean",
+        "varchar" => "character varying",
+        "timestamptz" => "timestamp with time zone",
+        "timestamp" => "timestamp without time zone",
+        "timetz" => "time with time zone",
+        "time" => "time without time zone",
+        v => v,
+    }
+    .to_owned()
+}
+
+fn same_type(backend: Backend, expected: &str, live: &str) -> bool {
+    match backend {
+        Backend::Sqlite => sqlite_affinity(expected) == sqlite_affinity(live),
+        Backend::Postgres => normalize_pg_type(expected) == normalize_pg_type(live),
+    }
+}
+
+/// Whether `ADD COLUMN` works on a table with rows, on both sqlite and postgres
+fn can_add_column(model: &RegisteredModel, field: &FieldDef) -> bool {
+    !model.is_primary_key(field)
+        && !field.options.unique
+        && (field.type_def.sql_type.nullable || field.options.default_sql.is_some())
+}
+
+/// Differences between a registered model and its table, see `SqlxDb.diff_schema`
+#[pyclass(frozen)]
+pub(crate) struct SqlxSchemaDiff {
+    #[pyo3(get)]
+    table_name: String,
+    #[pyo3(get)]
+    table_exists: bool,
+    /// Model columns missing from the table
+    #[pyo3(get)]
+    added: Vec<String>,
+    /// Added columns that existing rows can't get without `allow_destructive`: NOT NULL without
+    /// `default_sql`, UNIQUE or primary key
+    #[pyo3(get)]
+    unsafe_added: Vec<String>,
+    /// Table columns missing from the model
+    #[pyo3(get)]
+    removed: Vec<String>,
+    /// (column, table type, model type)
+    #[pyo3(get)]
+    retyped: Vec<(String, String, String)>,
+    /// (column, nullable in the table, nullable in the model), primary keys are not compared
+    #[pyo3(get)]
+    nullability: Vec<(String, bool, bool)>,
+    /// Names of the model's indexes missing from the table
+    #[pyo3(get)]
+    missing_indexes: Vec<String>,
+    /// (column, values of the model missing from the column's native postgres enum)
+    #[pyo3(get)]
+    missing_enum_labels: Vec<(String, Vec<String>)>,
+    /// Whether the table had rows, which required columns can't be added to
+    has_rows: bool,
+}
+
+impl SqlxSchemaDiff {
+    pub(crate) fn new(
+        py: Python<'_>,
+        model: &RegisteredModel,
+        backend: Backend,
+        live: Option<LiveTable>,
+    ) -> Self {
+        let Some(live) = live else {
+            return SqlxSchemaDiff {
+                table_name: model.table_name.clone(),
+                table_exists: false,
+                added: model.schema.iter().map(|v| v.column().to_owned()).collect(),
+                unsafe_added: Vec::new(),
+                removed: Vec::new(),
+                retyped: Vec::new(),
+                nullability: Vec::new(),
+                missing_indexes: indexed_fields(model)
+                    .map(|v| index_name(model, v))
+                    .collect(),
+                missing_enum_labels: Vec::new(),
+                has_rows: false,
+            };
+        };
+
+        let mut diff = SqlxSchemaDiff {
+            table_name: model.table_name.clone(),
+            table_exists: true,
+            added: Vec::new(),
+            unsafe_added: Vec::new(),
+            removed: live
+                .columns
+                .iter()
+                .filter(|c| !model.schema.iter().any(|v| v.column() == c.name))
+                .map(|c| c.name.clone())
+                .collect(),
+            retyped: Vec::new(),
+            nullability: Vec::new(),
+            missing_indexes: indexed_fields(model)
+                .map(|v| index_name(model, v))
+                .filter(|name| !live.indexes.contains(name))
+                .collect(),
+            missing_enum_labels: Vec::new(),
+            has_rows: live.has_rows,
+        };
+
+        for field in model.schema.iter() {
+            let Some(column) = live.columns.iter().find(|c| c.name == field.column()) else {
+                diff.added.push(field.column().to_owned());
+                if !can_add_column(model, field) {
+                    diff.unsafe_added.push(field.column().to_owned());
+                }
+                continue;
+            };
+
+            let expected = column_type(py, field, backend);
+            if !same_type(backend, &expected, &column.sql_type) {
+                diff.retyped.push((
+                    column.name.clone(),
+                    column.sql_type.clone(),
+                    expected.into_owned(),
+                ));
+            } else if let (Some(_), Some(choices)) = (&field.pg_enum, &field.type_def.choices) {
+                // Existing types are kept by `create_enum_type`, so new members have to be added
+                let missing: Vec<String> = choices
+                    .iter()
+                    .filter_map(|v| match v {
+                        Choice::Text(label) if !column.enum_labels.contains(label) => {
+                            Some(label.clone())
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                if !missing.is_empty() {
+                    diff.missing_enum_labels
+                        .push((column.name.clone(), missing));
+                }
+            }
+            let nullable = field.type_def.sql_type.nullable;
+            if !model.is_primary_key(field) && column.nullable != nullable {
+                diff.nullability
+                    .push((column.name.clone(), column.nullable, nullable));
+            }
+        }
+        diff
+    }
+
+    fn has_changes(&self) -> bool {
+        !(self.table_exists
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.retyped.is_empty()
+            && self.nullability.is_empty()
+            && self.missing_indexes.is_empty()
+            && self.missing_enum_labels.is_empty())
+    }
+}
+
+#[pymethods]
+impl SqlxSchemaDiff {
+    /// True if the table differs from the model
+    fn __bool__(&self) -> bool {
+        self.has_changes()
+    }
+
+    fn __repr__(&self) -> String {
+        if !self.table_exists {
+            return format!("<SqlxSchemaDiff {:?} missing table>", self.table_name);
+        }
+        let names = |names: Vec<&String>| {
+            let names = names.iter().map(|v| format!("{v:?}")).collect::<Vec<_>>();
+            format!("[{}]", names.join(", "))
+        };
+        format!(
+            "<SqlxSchemaDiff {:?} added={} unsafe_added={} removed={} retyped={} nullability={} missing_indexes={} missing_enum_labels={}>",
+            self.table_name,
+            names(self.added.iter().collect()),
+            names(self.unsafe_added.iter().collect()),
+            names(self.removed.iter().collect()),
+            names(self.retyped.iter().map(|v| &v.0).collect()),
+            names(self.nullability.iter().map(|v| &v.0).collect()),
+            names(self.missing_indexes.iter().collect()),
+            names(self.missing_enum_labels.iter().map(|v| &v.0).collect())
+        )
+    }
+}
+
+fn field_of<'m>(model: &'m RegisteredModel, column: &str) -> &'m FieldDef {
+    model
+        .schema
+        .iter()
+        .find(|v| v.column() == column)
+        .expect("Diff columns should be model columns")
+}
+
+/// Statements that migrate the table to the model
+///
+/// Adding columns, relaxing NOT NULL and adding enum values (postgres) and creating indexes are always applied.
+/// Dropping columns, changing types, adding NOT NULL and adding `unsafe_added` columns need
+/// `allow_destructive`, sqlite can only do those by rebuilding the table (dropping indexes not
+/// created by the model). Adding a required column (NOT NULL without `default_sql`) to a table
+/// with rows raises `ProgrammingError`, before any statement runs.
+pub(crate) fn migration_statements(
+    py: Python<'_>,
+    model: &RegisteredModel,
+    backend: Backend,
+    diff: &SqlxSchemaDiff,
+    allow_destructive: bool,
+) -> PyResult<Vec<String>> {
+    if !diff.table_exists {
+        return Ok(create_table(py, model, backend, true));
+    }
+    if allow_destructive && diff.has_rows {
+        let required: Vec<&str> = diff
+            .unsafe_added
+            .iter()
+            .filter(|v| {
+                let field = field_of(model, v);
+                !field.type_def.sql_type.nullable
+                    && field.options.default_sql.is_none()
+                    && !field.options.autoincrement
+            })
+            .map(String::as_str)
+            .collect();
+        if !required.is_empty() {
+            return Err(ProgrammingError::new_err(format!(
+                "Can't add required columns {} to {:?} which has rows, they need a default_sql",
+                required.join(", "),
+                model.table_name
+            )));
+        }
+    }
+
+    let table = quote_ident(&model.table_name);
+    let destructive = !diff.removed.is_empty()
+        || !diff.retyped.is_empty()
+        || !diff.nullability.is_empty()
+        || !diff.unsafe_added.is_empty();
+    if backend == Backend::Sqlite && allow_destructive && destructive {
+        let rebuilt = format!("{}__pysqlx_migrate", model.table_name);
+        let kept: Vec<&FieldDef> = model
+            .schema
+            .iter()
+            .filter(|v| !diff.added.iter().any(|c| c == v.column()))
+            .collect();
+        let columns = kept
+            .iter()
+            .map(|v| quote_ident(v.column()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        // Nulls of columns that become NOT NULL are replaced by their default, if any
+        let values = kept
+            .iter()
+            .map(
+                |v| match (&v.options.default_sql, v.type_def.sql_type.nullable) {
+                    (Some(default), false) => {
+                        format!("COALESCE({}, {default})", quote_ident(v.column()))
+                    }
+                    _ => quote_ident(v.column()),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut statements = vec![
+            create_table_as(py, model, backend, &rebuilt, false),
+            format!(
+                "INSERT INTO {} ({columns}) SELECT {values} FROM {table}",
+                quote_ident(&rebuilt)
+            ),
+            format!("DROP TABLE {table}"),
+            format!("ALTER TABLE {} RENAME TO {table}", quote_ident(&rebuilt)),
+        ];
+        // Indexes were dropped with the old table
+        statements.extend(indexed_fields(model).map(|v| create_index(model, v, false)));
+        return Ok(statements);
+    }
+
+    let mut statements = Vec::new();
+    // Enum types of new or retyped columns have to exist before the columns
+    if backend == Backend::Postgres {
+        let retyped = diff
+            .retyped
+            .iter()
+            .filter(|_| allow_destructive)
+            .map(|(column, ..)| column);
+        for column in diff.added.iter().chain(retyped) {
+            statements.extend(create_enum_type(field_of(model, column), true));
+        }
+    }
+    for column in diff.added.iter() {
+        if !allow_destructive && diff.unsafe_added.contains(column) {
+            continue;
+        }
+        let field = field_of(model, column);
+        statements.push(format!(
+            "ALTER TABLE {table} ADD COLUMN {}",
+            column_def(py, model, field, backend)
+        ));
+    }
+
+    if backend == Backend::Postgres {
+        for (column, labels) in diff.missing_enum_labels.iter() {
+            statements.extend(add_enum_values(field_of(model, column), labels));
+        }
+        for (column, _, nullable) in diff.nullability.iter() {
+            match nullable {
+                true => statements.push(format!(
+                    "ALTER TABLE {table} ALTER COLUMN {} DROP NOT NULL",
+                    quote_ident(column)
+                )),
+                false if allow_destructive => statements.push(format!(
+                    "ALTER TABLE {table} ALTER COLUMN {} SET NOT NULL",
+                    quote_ident(column)
+                )),
+                false => {}
+            }
+        }
+        if allow_destructive {
+            for (column, _, sql_type) in diff.retyped.iter() {
+                statements.push(format!(
+                    "ALTER TABLE {table} ALTER COLUMN {0} TYPE {sql_type} USING {0}::{sql_type}",
+                    quote_ident(column)
+                ));
+            }
+            for column in diff.removed.iter() {
+                statements.push(format!(
+                    "ALTER TABLE {table} DROP COLUMN {}",
+                    quote_ident(column)
+                ));
+            }
+        }
+    }
+
+    for field in indexed_fields(model) {
+        if diff.missing_indexes.contains(&index_name(model, field)) {
+            statements.push(create_index(model, field, true));
+        }
+    }
+    Ok(statements)
+}
+
+/// Bookkeeping table of `SqlxDb.run_migrations`
+const MIGRATIONS_TABLE: &str = "_pysqlx_migrations";
+
+/// A `NNNN_name.sql` migration file
+pub(crate) struct MigrationFile {
+    version: i64,
+    /// File name without extension
+    name: String,
+    sql: String,
+    /// Hex encoded sha256 of `sql`
+    checksum: String,
+}
+
+/// Reads the migration files of `dir` ordered by version, other files are ignored
+pub(crate) fn read_migrations(py: Python<'_>, dir: &Path) -> PyResult<Vec<MigrationFile>> {
+    let sha256 = py
+        .import(intern!(py, "hashlib"))?
+        .getattr(intern!(py, "sha256"))?;
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|v| v.to_str())
+            .and_then(|v| v.strip_suffix(".sql"))
+        else {
+            continue;
+        };
+        let Some(version) = name
+            .split_once('_')
+            .filter(|(v, _)| !v.is_empty() && v.bytes().all(|c| c.is_ascii_digit()))
+            .and_then(|(v, _)| v.parse::<i64>().ok())
+        else {
+            continue;
+        };
+
+        let sql = std::fs::read_to_string(&path)?;
+        let checksum = sha256
+            .call1((PyBytes::new(py, sql.as_bytes()),))?
+            .call_method0(intern!(py, "hexdigest"))?
+            .extract()?;
+        files.push(MigrationFile {
+            version,
+            name: name.to_owned(),
+            sql,
+            checksum,
+        });
+    }
+
+    files.sort_by_key(|v| v.version);
+    if let Some(v) = files.windows(2).find(|v| v[0].version == v[1].version) {
+        return Err(PyValueError::new_err(format!(
+            "Migrations {} and {} have the same version",
+            v[0].name, v[1].name
+        )));
+    }
+    Ok(files)
+}
+
+/// Applies the pending `files` in order, each in its own transaction
+///
+/// Fails before applying anything if an applied migration was edited since.
+/// Returns the names of the applied (or with `dry_run` the pending) migrations.
+pub(crate) async fn run_migrations(
+    pool: AnyPool,
+    backend: Backend,
+    files: Vec<MigrationFile>,
+    dry_run: bool,
+) -> PyResult<Vec<String>> {
+    let table = quote_ident(MIGRATIONS_TABLE);
+    let exists = introspect(pool.clone(), backend, MIGRATIONS_TABLE.to_owned())
+        .await
+        .map_err(to_pyerr)?
+        .is_some();
+
+    if !exists && !dry_run {
+        let applied_at = match backend {
+            Backend::Sqlite => "TEXT",
+            Backend::Postgres => "TIMESTAMPTZ",
+        };
+        pool.execute(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {table} (\n    \"version\" BIGINT PRIMARY KEY NOT NULL,\n    \"name\" TEXT NOT NULL,\n    \"checksum\" TEXT NOT NULL,\n    \"applied_at\" {applied_at} NOT NULL DEFAULT CURRENT_TIMESTAMP\n)"
+            )
+            .as_str(),
+        )
+        .await
+        .map_err(to_pyerr)?;
+    }
+
+    let applied: Vec<(i64, String, String)> = match exists {
+        true => sqlx::query_as(&format!("SELECT version, name, checksum FROM {table}"))
+            .fetch_all(&pool)
+            .await
+            .map_err(to_pyerr)?,
+        false => Vec::new(),
+    };
+    for (version, _, checksum) in applied.iter() {
+        if let Some(file) = files.iter().find(|v| v.version == *version) {
+            if &file.checksum != checksum {
+                return Err(ProgrammingError::new_err(format!(
+                    "Migration {} was edited after it was applied",
+                    file.name
+                )));
+            }
+        }
+    }
+
+    let pending = files
+        .into_iter()
+        .filter(|file| !applied.iter().any(|(version, ..)| *version == file.version));
+    if dry_run {
+        return Ok(pending.map(|file| file.name).collect());
+    }
+
+    let insert = format!(
+        "INSERT INTO {table} (version, name, checksum) VALUES ({}, {}, {})",
+        placeholder(backend, 0),
+        placeholder(backend, 1),
+        placeholder(backend, 2)
+    );
+    let mut names = Vec::new();
+    for file in pending {
+        let mut tx = pool.begin().await.map_err(to_pyerr)?;
+        tx.execute(file.sql.as_str())
+            .await
+            .map_err(|e| to_pyerr_context(e, &format!("Migration {} failed", file.name)))?;
+        tx.execute(
+            sqlx::query(&insert)
+                .bind(file.version)
+                .bind(file.name.clone())
+                .bind(file.checksum),
+        )
+        .await
+        .map_err(to_pyerr)?;
+        tx.commit().await.map_err(to_pyerr)?;
+        names.push(file.name);
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqlite_affinity_rules() {
+        assert_eq!(sqlite_affinity("INTEGER"), "INTEGER");
+        assert_eq!(sqlite_affinity("bigint"), "INTEGER");
+        // "INT" is checked before "CHAR"
+        assert_eq!(sqlite_affinity("CHARINT"), "INTEGER");
+        assert_eq!(sqlite_affinity("varchar(20)"), "TEXT");
+        assert_eq!(sqlite_affinity("CLOB"), "TEXT");
+        assert_eq!(sqlite_affinity("BLOB"), "BLOB");
+        assert_eq!(sqlite_affinity(""), "BLOB");
+        assert_eq!(sqlite_affinity("DOUBLE PRECISION"), "REAL");
+        assert_eq!(sqlite_affinity("FLOAT"), "REAL");
+        assert_eq!(sqlite_affinity("DECIMAL(10, 2)"), "NUMERIC");
+        assert_eq!(sqlite_affinity("BOOLEAN"), "NUMERIC");
+    }
+
+    #[test]
+    fn normalize_pg_type_names() {
+        assert_eq!(normalize_pg_type("BIGINT"), "bigint");
+        assert_eq!(normalize_pg_type("int8"), "bigint");
+        assert_eq!(normalize_pg_type("DOUBLE PRECISION"), "double precision");
+        assert_eq!(normalize_pg_type("float8"), "double precision");
+        assert_eq!(normalize_pg_type("NUMERIC(12, 4)"), "numeric");
+        assert_eq!(normalize_pg_type("varchar(20)"), "character varying");
+        assert_eq!(normalize_pg_type("TIMESTAMPTZ"), "timestamp with time zone");
+        assert_eq!(normalize_pg_type("TIME"), "time without time zone");
+        assert_eq!(normalize_pg_type("\"item_color\""), "item_color");
+        assert_eq!(normalize_pg_type("jsonb"), "jsonb");
+    }
+
+    #[test]
+    fn same_type_per_backend() {
+        assert!(same_type(Backend::Sqlite, "INTEGER", "int"));
+        assert!(!same_type(Backend::Sqlite, "TEXT", "BLOB"));
+        assert!(same_type(Backend::Postgres, "BIGINT", "bigint"));
+        assert!(!same_type(Backend::Postgres, "INTEGER", "bigint"));
+    }
+}
@@ -0,0 +1,704 @@
+use std::sync::Arc;
+
+use eyre::Result;
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    intern,
+    prelude::*,
+    types::{PyBool, PyDict, PyFloat, PyInt, PyNone, PyString, PyTuple, PyType},
+    PyTypeInfo,
+};
+
+use crate::{
+    codec::{BlobEncoding, Codec, EncodingKind},
+    crud::ModelStatements,
+    ddl::quote_ident,
+    decode::Converter,
+    enums::{choices_affinity, enum_choices, is_enum_type, literal_args, literal_choices, Choice},
+    Backend, SqlType, TypeAffinity, PY_TYPE_LUT,
+};
+
+/// Resolved type of a model field
+pub(crate) struct TypeDef {
+    pub(crate) sql_type: SqlType,
+    /// Python type of the (non-null) values, used to convert them back
+    pub(crate) py_type: Py<PyType>,
+    /// Allowed values of enum and `Literal` types
+    pub(crate) choices: Option<Vec<Choice>>,
+}
+
+/// `Some(members)` when `anno` is a union (`X | Y` or `typing.Union[X, Y]`/`Optional[X]`)
+fn union_args<'py>(anno: &Bound<'py, PyAny>) -> PyResult<Option<Bound<'py, PyTuple>>> {
+    let py = anno.py();
+    let typing_mod = py.import(intern!(py, "typing"))?;
+    let origin = typing_mod
+        .getattr(intern!(py, "get_origin"))?
+        .call1((anno,))?;
+    // `X | Y` (`types.UnionType`) only exists from python 3.10
+    let is_union = origin.is(&typing_mod.getattr(intern!(py, "Union"))?)
+        || py
+            .import(intern!(py, "types"))?
+            .getattr(intern!(py, "UnionType"))
+            .is_ok_and(|v| origin.is(&v));
+
+    if is_union {
+        let tuple_types = anno.getattr(intern!(py, "__args__"))?.downcast_into()?;
+        return Ok(Some(tuple_types));
+    }
+    Ok(None)
+}
+
+/// int, float or bool, which unions can mix
+fn is_builtin_number(py_type: &Bound<'_, PyType>) -> bool {
+    let py = py_type.py();
+    py_type.is(&PyInt::type_object(py))
+        || py_type.is(&PyFloat::type_object(py))
+        || py_type.is(&PyBool::type_object(py))
+}
+
+/// `Some((inner, metadata))` when `anno` is `Annotated[inner, *metadata]`
+fn annotated_args<'py>(
+    anno: &Bound<'py, PyAny>,
+) -> PyResult<Option<(Bound<'py, PyAny>, Bound<'py, PyTuple>)>> {
+    let py = anno.py();
+    let typing_mod = py.import(intern!(py, "typing"))?;
+    let origin = typing_mod
+        .getattr(intern!(py, "get_origin"))?
+        .call1((anno,))?;
+
+    // `typing.Annotated` only exists from python 3.9
+    let Ok(annotated) = typing_mod.getattr(intern!(py, "Annotated")) else {
+        return Ok(None);
+    };
+    if origin.is(&annotated) {
+        let inner = anno.getattr(intern!(py, "__origin__"))?;
+        let metadata = anno.getattr(intern!(py, "__metadata__"))?.downcast_into()?;
+        return Ok(Some((inner, metadata)));
+    }
+    Ok(None)
+}
+
+/// Resolves a type annotation into its `TypeDef`
+///
+/// Plain types and generic aliases (e.g. `dict[str, str]`) are looked up in `PY_TYPE_LUT`
+/// by their (origin) type, `Annotated` is resolved by its inner type.
+/// `<type> | None` (or `Optional[<type>]`) is nullable, unions of int/float/bool resolve to `Numeric`
+/// with the first (most general) member as `py_type`, stored (and returned) as floats (see `ddl::column_type`).
+/// Other unions are only supported between members of the same type, e.g. `Literal["a"] | str`.
+/// Enums and `Literal` resolve by their values, which have to be either all str or all int/bool.
+pub(crate) fn try_get_root_sql_type<'py>(anno: &Bound<'py, PyAny>) -> Result<TypeDef> {
+    let py = anno.py();
+
+    if let Some(tuple_types) = union_args(anno)? {
+        let mut nullable = false;
+        let mut members = Vec::with_capacity(tuple_types.len());
+        for v in tuple_types.iter() {
+            if v.is(&PyNone::get(py).get_type()) {
+                nullable = true;
+            } else {
+                members.push(try_get_root_sql_type(&v)?);
+            }
+        }
+
+        // `<type> | None` is the nullable `<type>`, any further members have to be compatible with it
+        let mut members = members.into_iter();
+        let mut type_def = members
+            .next()
+            .ok_or_else(|| eyre::eyre!("{anno} has no non-None type"))?;
+        for v in members {
+            let (a, b) = (type_def.py_type.bind(py), v.py_type.bind(py));
+            // Only members of the same type or builtin numbers merge, other mixes (even of one affinity,
+            // like `datetime | date`) would need more than one conversion and are rejected
+            type_def.sql_type.affinity = if a.is(b) {
+                v.sql_type.affinity
+            } else if is_builtin_number(a) && is_builtin_number(b) {
+                match type_def.sql_type.affinity == v.sql_type.affinity {
+                    true => v.sql_type.affinity,
+                    false => TypeAffinity::Numeric,
+                }
+            } else {
+                return Err(eyre::eyre!(
+                    "Unsupported union {anno}, only `<type> | None`, unions of numeric types (int, float) and unions of the same type are supported"
+                ));
+            };
+            // e.g. `bool | int` holds ints
+            if type_def.py_type.bind(py).is_subclass(v.py_type.bind(py))? {
+                type_def.py_type = v.py_type;
+            }
+            // e.g. `Literal["a"] | Literal["b"]`, any member without choices allows all values
+            type_def.choices = match (type_def.choices.take(), v.choices) {
+                (Some(mut choices), Some(other)) => {
+                    choices.extend(other);
+                    Some(choices)
+                }
+                _ => None,
+            };
+        }
+        type_def.sql_type.nullable |= nullable;
+        return Ok(type_def);
+    }
+
+    if let Some((inner, _)) = annotated_args(anno)? {
+        return try_get_root_sql_type(&inner);
+    }
+
+    if let Some(args) = literal_args(anno)? {
+        let none = PyNone::get(py);
+        let values = PyTuple::new(
+            py,
+            args.iter().filter(|v| !v.is(&*none)).collect::<Vec<_>>(),
+        )?;
+        let choices = literal_choices(&values)?;
+        let affinity = choices_affinity(&choices).map_err(|e| eyre::eyre!("{anno} {e}"))?;
+        let first_type = values
+            .get_item(0)
+            .map_err(|_| eyre::eyre!("{anno} has no non-None value"))?
+            .get_type();
+        // Values are returned as their type, which is the enum of `Literal[Color.RED, ...]`
+        let py_type =
+            if is_enum_type(&first_type) && values.iter().all(|v| v.get_type().is(&first_type)) {
+                first_type
+            } else if affinity == TypeAffinity::Text {
+                PyString::type_object(py)
+            } else if choices.iter().all(|v| matches!(v, Choice::Bool(_))) {
+                PyBool::type_object(py)
+            } else {
+                PyInt::type_object(py)
+            };
+        return Ok(TypeDef {
+            sql_type: SqlType {
+                affinity,
+                nullable: values.len() < args.len(),
+            },
+            py_type: py_type.unbind(),
+            choices: Some(choices),
+        });
+    }
+
+    let origin = py
+        .import(intern!(py, "typing"))?
+        .getattr(intern!(py, "get_origin"))?
+        .call1((anno,))?;
+    let ptype = match origin.is_none() {
+        true => anno.downcast::<PyType>(),
+        false => origin.downcast::<PyType>(),
+    }
+    .map_err(|_| eyre::eyre!("{anno} is not a type"))?;
+
+    // Enums are stored by the value of their members
+    if is_enum_type(ptype) {
+        let choices = enum_choices(ptype)?;
+        let affinity = choices_affinity(&choices).map_err(|e| eyre::eyre!("{anno} {e}"))?;
+        return Ok(TypeDef {
+            sql_type: SqlType {
+                affinity,
+                nullable: false,
+            },
+            py_type: ptype.clone().unbind(),
+            choices: Some(choices),
+        });
+    }
+
+    let lut = PY_TYPE_LUT
+        .get()
+        .expect("pysqlx module was not initialized");
+    let sql_type = lut
+        .get_or_index(ptype.clone())
+        .map_err(|_| eyre::eyre!("No valid sqltype found for {anno}"))?;
+
+    Ok(TypeDef {
+        sql_type,
+        py_type: ptype.clone().unbind(),
+        choices: None,
+    })
+}
+
+/// Column options from msgspec `Meta(extra={...})` annotations
+#[derive(Default)]
+pub(crate) struct ColumnOptions {
+    pub(crate) column_name: Option<String>,
+    pub(crate) primary_key: bool,
+    pub(crate) autoincrement: bool,
+    pub(crate) unique: bool,
+    pub(crate) index: bool,
+    /// Raw SQL expression used as column default
+    pub(crate) default_sql: Option<String>,
+    /// Encoding of a dict/list field, overriding the database's `blob_encoding`
+    pub(crate) encoding: Option<Arc<BlobEncoding>>,
+    /// `NUMERIC(precision, scale)` of a Decimal field on postgres
+    pub(crate) precision: Option<u32>,
+    pub(crate) scale: Option<u32>,
+    /// Restricts an enum/`Literal` column to its values, by `CHECK` or a native postgres enum
+    pub(crate) check: bool,
+}
+
+impl ColumnOptions {
+    const KEYS: [&str; 10] = [
+        "column_name",
+        "primary_key",
+        "autoincrement",
+        "unique",
+        "index",
+        "check",
+        "default_sql",
+        "encoding",
+        "precision",
+        "scale",
+    ];
+
+    /// Invalid values raise `TypeError`, unknown keys (e.g. typos) `ValueError`
+    fn read_extra(&mut self, extra: &Bound<'_, PyDict>) -> PyResult<()> {
+        fn flag(k: &str, v: &Bound<'_, PyAny>) -> PyResult<bool> {
+            v.extract::<bool>()
+                .map_err(|_| PyTypeError::new_err(format!("`{k}` must be a bool, got {v}")))
+        }
+        fn digits(k: &str, v: &Bound<'_, PyAny>) -> PyResult<u32> {
+            v.extract::<u32>().map_err(|_| {
+                PyTypeError::new_err(format!("`{k}` must be a non-negative int, got {v}"))
+            })
+        }
+
+        for (k, v) in extra.iter() {
+            match k.extract::<&str>()? {
+                "column_name" => self.column_name = Some(v.extract()?),
+                "primary_key" => self.primary_key = flag("primary_key", &v)?,
+                "autoincrement" => self.autoincrement = flag("autoincrement", &v)?,
+                "unique" => self.unique = flag("unique", &v)?,
+                "index" => self.index = flag("index", &v)?,
+                "check" => self.check = flag("check", &v)?,
+                "default_sql" => self.default_sql = Some(v.extract()?),
+                "encoding" => self.encoding = Some(Arc::new(BlobEncoding::from_py(&v)?)),
+                "precision" => self.precision = Some(digits("precision", &v)?),
+                "scale" => self.scale = Some(digits("scale", &v)?),
+                k => {
+                    return Err(PyValueError::new_err(format!(
+                        "Unknown key {k:?}, expected one of {}",
+                        Self::KEYS.join(", ")
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the options of all (nested) `Annotated` metadata of `anno`, e.g. in `Optional[Annotated[...]]`
+    fn read_annotation(&mut self, anno: &Bound<'_, PyAny>) -> PyResult<()> {
+        let py = anno.py();
+        if let Some(tuple_types) = union_args(anno)? {
+            for v in tuple_types.iter() {
+                self.read_annotation(&v)?;
+            }
+        } else if let Some((inner, metadata)) = annotated_args(anno)? {
+            for meta in metadata.iter() {
+                let extra = match meta.getattr(intern!(py, "extra")) {
+                    Ok(extra) if !extra.is_none() => extra,
+                    _ => continue,
+                };
+                self.read_extra(extra.downcast()?)?;
+            }
+            self.read_annotation(&inner)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct FieldDef {
+    /// Attribute name on the model
+    pub(crate) name: String,
+    pub(crate) type_def: TypeDef,
+    pub(crate) options: ColumnOptions,
+    pub(crate) converter: Converter,
+    /// Quoted name of the native enum type on postgres, for `check` fields with str values
+    pub(crate) pg_enum: Option<String>,
+}
+
+impl FieldDef {
+    pub(crate) fn column(&self) -> &str {
+        self.options.column_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Encoding of dict/list fields, `None` for all other fields
+    pub(crate) fn encoding(&self) -> Option<&BlobEncoding> {
+        match &self.converter {
+            Converter::Encoded(encoding) => Some(encoding),
+            _ => None,
+        }
+    }
+
+    /// Postgres type of values that the Any driver can't bind or decode, these are bound as text
+    /// and cast (`$1::TIMESTAMP`) and selected as text (`"column"::text`)
+    pub(crate) fn pg_text_type(&self) -> Option<&str> {
+        if let Some(pg_enum) = &self.pg_enum {
+            return Some(pg_enum);
+        }
+        match &self.converter {
+            Converter::Encoded(encoding) if encoding.kind == EncodingKind::Json => Some("JSONB"),
+            Converter::Temporal(temporal) => Some(temporal.pg_type()),
+            Converter::Uuid => Some("UUID"),
+            Converter::Decimal => Some("NUMERIC"),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct RegisteredModel {
+    pub(crate) model: Py<PyType>,
+    pub(crate) table_name: String,
+    /// Field name of the primary key, if any
+    pub(crate) primary_key: Option<String>,
+    /// Fields in declaration order
+    pub(crate) schema: Vec<FieldDef>,
+    /// Primary key based statements, `None` without a primary key
+    pub(crate) statements: Option<ModelStatements>,
+    /// Codec of the database the model is registered with
+    pub(crate) codec: Arc<Codec>,
+}
+
+/// `ExampleModel` -> `example_model`, `HTTPLog` -> `http_log`
+fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|v| v.is_lowercase());
+            if prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next_lower) {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+impl RegisteredModel {
+    pub(crate) fn is_primary_key(&self, field: &FieldDef) -> bool {
+        self.primary_key.as_deref() == Some(field.name.as_str())
+    }
+
+    pub(crate) fn primary_key_field(&self) -> Option<&FieldDef> {
+        self.schema.iter().find(|v| self.is_primary_key(v))
+    }
+
+    /// Reflects a model class (msgspec `Struct` or any class with type annotations)
+    pub(crate) fn from_model(model: &Bound<'_, PyType>, codec: &Arc<Codec>) -> PyResult<Self> {
+        let py = model.py();
+        let typing_mod = py.import(intern!(py, "typing"))?;
+        let qualname = model.qualname()?;
+
+        // Resolves string annotations and includes base classes, `include_extras` keeps `Annotated` (python 3.9+)
+        let kwargs = PyDict::new(py);
+        if py.version_info() >= (3, 9) {
+            kwargs.set_item(intern!(py, "include_extras"), true)?;
+        }
+        let hints = typing_mod
+            .getattr(intern!(py, "get_type_hints"))?
+            .call((model,), Some(&kwargs))
+            .map_err(|e| {
+                PyTypeError::new_err(format!(
+                    "Expected {qualname} to have resolvable type annotations (msgspec or pydantic): {e}"
+                ))
+            })?;
+        let hints = hints.downcast::<PyDict>()?;
+
+        // msgspec lists its fields (without ClassVars) in order, other classes use every annotation
+        let names: Vec<String> = match model.getattr(intern!(py, "__struct_fields__")) {
+            Ok(fields) => fields.downcast::<PyTuple>()?.extract()?,
+            Err(_) => {
+                let class_var = typing_mod.getattr(intern!(py, "ClassVar"))?;
+                let get_origin = typing_mod.getattr(intern!(py, "get_origin"))?;
+                let mut names = Vec::with_capacity(hints.len());
+                for (k, v) in hints.iter() {
+                    if !get_origin.call1((&v,))?.is(&class_var) && !v.is(&class_var) {
+                        names.push(k.downcast::<PyString>()?.to_str()?.to_owned());
+                    }
+                }
+                names
+            }
+        };
+
+        let mut schema = Vec::with_capacity(names.len());
+        for name in names {
+            let anno = hints.get_item(&name)?.ok_or_else(|| {
+                PyTypeError::new_err(format!("Field {name} of {qualname} has no type annotation"))
+            })?;
+            let mut type_def = try_get_root_sql_type(&anno).map_err(|e| {
+                PyTypeError::new_err(format!("Field {name} of {qualname} is not supported: {e}"))
+            })?;
+            let mut options = ColumnOptions::default();
+            options.read_annotation(&anno).map_err(|e| {
+                PyErr::from_type(
+                    e.get_type(py),
+                    format!(
+                        "Invalid metadata on field {name} of {qualname}: {}",
+                        e.value(py)
+                    ),
+                )
+            })?;
+            let encoding = options.encoding.as_ref().unwrap_or(&codec.blob_encoding);
+            let converter = Converter::for_type(py, &type_def, encoding)?;
+            if options.precision.is_some() || options.scale.is_some() {
+                match (&converter, options.precision, options.scale) {
+                    (Converter::Decimal, Some(precision), scale)
+                        if scale.unwrap_or(0) <= precision => {}
+                    (Converter::Decimal, _, _) => {
+                        return Err(PyTypeError::new_err(format!(
+                            "Field {name} of {qualname} needs a precision of at least its scale"
+                        )))
+                    }
+                    _ => {
+                        return Err(PyTypeError::new_err(format!(
+                            "Field {name} of {qualname} has a precision/scale, which only applies to Decimal fields"
+                        )))
+                    }
+                }
+            }
+            if options.check && type_def.choices.is_none() {
+                return Err(PyTypeError::new_err(format!(
+                    "Field {name} of {qualname} has check, which only applies to enum and Literal fields"
+                )));
+            }
+            if let Converter::Uuid = converter {
+                type_def.sql_type.affinity = codec.uuid_affinity();
+            }
+            if options.encoding.is_some() && !matches!(converter, Converter::Encoded(_)) {
+                return Err(PyTypeError::new_err(format!(
+                    "Field {name} of {qualname} has an encoding, which only applies to dict and list fields"
+                )));
+            }
+            schema.push(FieldDef {
+                name,
+                type_def,
+                options,
+                converter,
+                pg_enum: None,
+            });
+        }
+
+        if schema.is_empty() {
+            return Err(PyTypeError::new_err(format!("{qualname} has no fields")));
+        }
+
+        // An explicit `primary_key` takes precedence over the convention of a field named `id`
+        let mut explicit = schema.iter().filter(|v| v.options.primary_key);
+        let primary_key = match (explicit.next(), explicit.next()) {
+            (Some(_), Some(_)) => {
+                return Err(PyTypeError::new_err(format!(
+                    "{qualname} has multiple primary keys, composite primary keys are not supported"
+                )))
+            }
+            (Some(v), None) => Some(v),
+            (None, _) => schema.iter().find(|v| v.name == "id"),
+        };
+        // `None` is only allowed for generated keys
+        let nullable_pk =
+            primary_key.filter(|v| v.type_def.sql_type.nullable && !v.options.autoincrement);
+        if let Some(v) = nullable_pk {
+            return Err(PyTypeError::new_err(format!(
+                "Primary key {} of {qualname} can't be nullable",
+                v.name
+            )));
+        }
+        for v in schema.iter().filter(|v| v.options.autoincrement) {
+            let is_pk = primary_key.is_some_and(|pk| pk.name == v.name);
+            if !is_pk || v.type_def.sql_type.affinity != TypeAffinity::Integer {
+                return Err(PyTypeError::new_err(format!(
+                    "Field {} of {qualname} can only be autoincrement as integer primary key",
+                    v.name
+                )));
+            }
+        }
+        let primary_key = primary_key.map(|v| v.name.clone());
+
+        let table_name = match model.getattr(intern!(py, "__tablename__")) {
+            Ok(v) => v.extract::<String>()?,
+            Err(_) => to_snake_case(&model.name()?.to_string()),
+        };
+        // Named like indexes, types are per column so they're created and migrated with it
+        for v in schema.iter_mut().filter(|v| v.options.check) {
+            let is_text = v.type_def.sql_type.affinity == TypeAffinity::Text;
+            if is_text && codec.backend == Backend::Postgres {
+                v.pg_enum = Some(quote_ident(&format!("{table_name}_{}", v.column())));
+            }
+        }
+
+        let mut registered = RegisteredModel {
+            model: model.clone().unbind(),
+            table_name,
+            primary_key,
+            schema,
+            statements: None,
+            codec: codec.clone(),
+        };
+        registered.statements = ModelStatements::new(&registered, codec.backend);
+        Ok(registered)
+    }
+}
+
+/// Schema of a model registered with `SqlxDb.register_model`
+#[pyclass(frozen)]
+pub(crate) struct SqlxModelSchema(pub(crate) Arc<RegisteredModel>);
+
+#[pymethods]
+impl SqlxModelSchema {
+    #[getter]
+    fn model(&self, py: Python<'_>) -> Py<PyType> {
+        self.0.model.clone_ref(py)
+    }
+
+    #[getter]
+    fn table_name(&self) -> &str {
+        &self.0.table_name
+    }
+
+    #[getter]
+    fn primary_key(&self) -> Option<&str> {
+        self.0.primary_key.as_deref()
+    }
+
+    #[getter]
+    fn fields(&self) -> Vec<SqlxField> {
+        (0..self.0.schema.len())
+            .map(|idx| SqlxField {
+                model: self.0.clone(),
+                idx,
+            })
+            .collect()
+    }
+
+    fn __repr__(&self) -> String {
+        let fields = self
+            .0
+            .schema
+            .iter()
+            .map(|v| v.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let primary_key = match &self.0.primary_key {
+            Some(v) => format!("{v:?}"),
+            None => "None".to_owned(),
+        };
+        format!(
+            "<SqlxModelSchema table_name={:?} primary_key={primary_key} fields=[{fields}]>",
+            self.0.table_name
+        )
+    }
+}
+
+/// A single field of a `SqlxModelSchema`
+#[pyclass(frozen)]
+pub(crate) struct SqlxField {
+    model: Arc<RegisteredModel>,
+    idx: usize,
+}
+
+impl SqlxField {
+    fn def(&self) -> &FieldDef {
+        &self.model.schema[self.idx]
+    }
+}
+
+#[pymethods]
+impl SqlxField {
+    #[getter]
+    fn name(&self) -> &str {
+        &self.def().name
+    }
+
+    #[getter]
+    fn py_type(&self, py: Python<'_>) -> Py<PyType> {
+        self.def().type_def.py_type.clone_ref(py)
+    }
+
+    /// SQLite style type affinity, e.g. `"INTEGER"`
+    #[getter]
+    fn affinity(&self) -> &'static str {
+        self.def().type_def.sql_type.affinity.as_str()
+    }
+
+    #[getter]
+    fn nullable(&self) -> bool {
+        self.def().type_def.sql_type.nullable
+    }
+
+    #[getter]
+    fn column_name(&self) -> &str {
+        self.def().column()
+    }
+
+    #[getter]
+    fn primary_key(&self) -> bool {
+        self.model.is_primary_key(self.def())
+    }
+
+    #[getter]
+    fn autoincrement(&self) -> bool {
+        self.def().options.autoincrement
+    }
+
+    #[getter]
+    fn unique(&self) -> bool {
+        self.def().options.unique
+    }
+
+    #[getter]
+    fn index(&self) -> bool {
+        self.def().options.index
+    }
+
+    #[getter]
+    fn default_sql(&self) -> Option<&str> {
+        self.def().options.default_sql.as_deref()
+    }
+
+    #[getter]
+    fn check(&self) -> bool {
+        self.def().options.check
+    }
+
+    #[getter]
+    fn precision(&self) -> Option<u32> {
+        self.def().options.precision
+    }
+
+    #[getter]
+    fn scale(&self) -> Option<u32> {
+        self.def().options.scale
+    }
+
+    /// `"json"`, `"msgpack"` or `"custom"` for dict/list fields
+    #[getter]
+    fn encoding(&self) -> Option<&'static str> {
+        self.def().encoding().map(|v| v.kind.as_str())
+    }
+
+    fn __repr__(&self) -> String {
+        let def = self.def();
+        format!(
+            "<SqlxField {} {}{}>",
+            def.name,
+            self.affinity(),
+            if def.type_def.sql_type.nullable {
+                ""
+            } else {
+                " NOT NULL"
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snake_case_table_names() {
+        assert_eq!(to_snake_case("Item"), "item");
+        assert_eq!(to_snake_case("ExampleModel"), "example_model");
+        assert_eq!(to_snake_case("HTTPRequest"), "http_request");
+        assert_eq!(to_snake_case("UserV2Token"), "user_v2_token");
+        assert_eq!(to_snake_case("ABC"), "abc");
+        assert_eq!(to_snake_case("already_snake"), "already_snake");
+        assert_eq!(to_snake_case("Über"), "über");
+    }
+}
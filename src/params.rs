@@ -0,0 +1,508 @@
+use pyo3::{
+    exceptions::{PyKeyError, PyTypeError},
+    intern,
+    prelude::*,
+    types::{PyBool, PyBytes, PyDict, PyList, PyString, PyTuple},
+    PyTypeInfo,
+};
+use sqlx::{any::AnyArguments, Arguments, Encode, Type};
+
+use crate::{
+    codec::{BlobEncoding, Codec},
+    decimal::{self, is_decimal_type},
+    enums::enum_value,
+    model::FieldDef,
+    temporal::{to_iso, Temporal},
+    uuid::is_uuid_type,
+    Backend, TypeAffinity, PY_TYPE_LUT,
+};
+
+/// Owned arguments; every value is copied out of python so the arguments can outlive the GIL
+pub(crate) type QueryArgs = AnyArguments<'static>;
+
+#[inline]
+fn add<T>(args: &mut QueryArgs, value: T) -> PyResult<()>
+where
+    T: 'static + Encode<'static, sqlx::Any> + Type<sqlx::Any>,
+{
+    args.add(value)
+        .map_err(|e| PyTypeError::new_err(format!("Failed to encode parameter: {e}")))
+}
+
+/// How the placeholder of a parameter is rewritten on postgres, see `cast_placeholders`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PgCast {
+    /// Kept as is
+    Plain,
+    /// Replaced by an untyped `NULL`, as the Any driver only binds typed nulls
+    Null,
+    /// Cast to the type (`$1::TIMESTAMPTZ`), for values the Any driver can only bind as text
+    Type(&'static str),
+}
+
+/// Converts a single python value into a sqlx argument based on the `SqlType` registered in `PY_TYPE_LUT`
+///
+/// Dicts and lists are encoded with the database's `blob_encoding`, enum members are bound by their value.
+/// Returns how the value's placeholder has to be rewritten on postgres.
+pub(crate) fn bind_value(
+    args: &mut QueryArgs,
+    value: &Bound<'_, PyAny>,
+    codec: &Codec,
+) -> PyResult<PgCast> {
+    if value.is_none() {
+        // The Any driver has no untyped null, this is an int4 null on postgres so its placeholder is replaced
+        add(args, Option::<i32>::None)?;
+        return Ok(PgCast::Null);
+    }
+    if let Some(value) = enum_value(value)? {
+        return bind_value(args, &value, codec);
+    }
+
+    let lut = PY_TYPE_LUT
+        .get()
+        .expect("pysqlx module was not initialized");
+    let sql_type = lut.get_or_index(value.get_type()).map_err(|_| {
+        PyTypeError::new_err(format!(
+            "Cannot bind parameter of type {:?}",
+            value
+                .get_type()
+                .qualname()
+                .map(|v| v.to_string())
+                .unwrap_or_default()
+        ))
+    })?;
+
+    match sql_type.affinity {
+        // bool is a subclass of int, but should be bound as a proper boolean for postgres
+        TypeAffinity::Integer if value.is_exact_instance_of::<PyBool>() => {
+            add(args, value.extract::<bool>()?)?
+        }
+        TypeAffinity::Integer => add(args, value.extract::<i64>()?)?,
+        TypeAffinity::Real => add(args, value.extract::<f64>()?)?,
+        TypeAffinity::Text => match value.downcast::<PyString>() {
+            Ok(text) => add(args, text.to_str()?.to_owned())?,
+            Err(_) if is_uuid_type(&value.get_type()) => {
+                bind_uuid(args, value, codec)?;
+                return Ok(PgCast::Type("UUID"));
+            }
+            // datetime, date and time (see `temporal`)
+            Err(_) => {
+                add(args, to_iso(value, codec)?)?;
+                if let Some(temporal) = Temporal::of_type(&value.get_type()) {
+                    return Ok(PgCast::Type(temporal.pg_type()));
+                }
+            }
+        },
+        TypeAffinity::Blob => match value.downcast::<PyBytes>() {
+            Ok(bytes) => add(args, bytes.as_bytes().to_vec())?,
+            Err(_) => bind_encoded(args, &codec.blob_encoding, codec.backend, value)?,
+        },
+        TypeAffinity::Numeric if is_decimal_type(&value.get_type()) => {
+            add(args, decimal::to_text(value)?)?;
+            return Ok(PgCast::Type("NUMERIC"));
+        }
+        TypeAffinity::Numeric => match value.extract::<i64>() {
+            Ok(v) => add(args, v)?,
+            Err(_) => add(args, value.extract::<f64>()?)?,
+        },
+    }
+    Ok(PgCast::Plain)
+}
+
+/// Placeholder of the `idx`th (0 based) parameter
+pub(crate) fn placeholder(backend: Backend, idx: usize) -> String {
+    match backend {
+        Backend::Sqlite => "?".to_owned(),
+        Backend::Postgres => format!("${}", idx + 1),
+    }
+}
+
+/// Binds a UUID as 16 bytes or as its text, see `Codec::uuid_affinity`
+fn bind_uuid(args: &mut QueryArgs, value: &Bound<'_, PyAny>, codec: &Codec) -> PyResult<()> {
+    match codec.uuid_affinity() {
+        TypeAffinity::Blob => add(
+            args,
+            value
+                .getattr(intern!(value.py(), "bytes"))?
+                .downcast::<PyBytes>()?
+                .as_bytes()
+                .to_vec(),
+        ),
+        _ => add(args, value.str()?.to_str()?.to_owned()),
+    }
+}
+
+/// Encodes a dict/list value, text for `jsonb` (see `BlobEncoding::is_jsonb`) and bytes otherwise
+fn bind_encoded(
+    args: &mut QueryArgs,
+    encoding: &BlobEncoding,
+    backend: Backend,
+    value: &Bound<'_, PyAny>,
+) -> PyResult<()> {
+    let encoded = encoding.encode(value)?;
+    match encoded.downcast::<PyString>() {
+        Ok(text) if encoding.is_jsonb(backend) => add(args, text.to_str()?.to_owned()),
+        Ok(text) => add(args, text.to_str()?.as_bytes().to_vec()),
+        Err(_) => add(args, encoded.downcast::<PyBytes>()?.as_bytes().to_vec()),
+    }
+}
+
+/// Converts the value of a model field into a sqlx argument based on its `TypeDef`
+///
+/// Unlike `bind_value`, nulls are typed by the field so postgres accepts them without a rewrite.
+pub(crate) fn bind_field(
+    args: &mut QueryArgs,
+    field: &FieldDef,
+    value: &Bound<'_, PyAny>,
+    codec: &Codec,
+) -> PyResult<()> {
+    let py = value.py();
+    if let Some(encoding) = field.encoding() {
+        return match value.is_none() {
+            true if encoding.is_jsonb(codec.backend) => add(args, Option::<String>::None),
+            true => add(args, Option::<Vec<u8>>::None),
+            false => bind_encoded(args, encoding, codec.backend, value),
+        };
+    }
+
+    let is_bool = || field.type_def.py_type.bind(py).is(&PyBool::type_object(py));
+
+    match (&field.type_def.sql_type.affinity, value.is_none()) {
+        (TypeAffinity::Integer, true) if is_bool() => add(args, Option::<bool>::None),
+        (TypeAffinity::Integer, true) => add(args, Option::<i64>::None),
+        (TypeAffinity::Real | TypeAffinity::Numeric, true) => add(args, Option::<f64>::None),
+        (TypeAffinity::Text, true) => add(args, Option::<String>::None),
+        (TypeAffinity::Blob, true) => add(args, Option::<Vec<u8>>::None),
+        // Placeholders of fields are cast by `FieldDef::pg_text_type` where needed
+        (_, false) => bind_value(args, value, codec).map(|_| ()),
+    }
+}
+
+/// Converts query parameters into `AnyArguments`
+///
+/// `params` may be a tuple/list (positional, using the backend's native placeholders)
+/// or a dict, in which case `:name` placeholders are rewritten to positional ones.
+/// Returns the (possibly rewritten, see `cast_placeholders`) query alongside the arguments.
+pub(crate) fn bind_params(
+    query: &str,
+    params: Option<&Bound<'_, PyAny>>,
+    codec: &Codec,
+) -> PyResult<(String, QueryArgs)> {
+    let mut args = QueryArgs::default();
+    let Some(params) = params.filter(|v| !v.is_none()) else {
+        return Ok((query.to_owned(), args));
+    };
+
+    let mut casts = Vec::new();
+    let query = if let Ok(tuple) = params.downcast::<PyTuple>() {
+        args.reserve(tuple.len(), 0);
+        for v in tuple.iter() {
+            casts.push(bind_value(&mut args, &v, codec)?);
+        }
+        query.to_owned()
+    } else if let Ok(list) = params.downcast::<PyList>() {
+        args.reserve(list.len(), 0);
+        for v in list.iter() {
+            casts.push(bind_value(&mut args, &v, codec)?);
+        }
+        query.to_owned()
+    } else if let Ok(dict) = params.downcast::<PyDict>() {
+        let (query, names) = rewrite_named(query, codec.backend);
+        args.reserve(names.len(), 0);
+        for name in names.iter() {
+            let v = dict
+                .get_item(name)?
+                .ok_or_else(|| PyKeyError::new_err(format!("Missing query parameter {name:?}")))?;
+            casts.push(bind_value(&mut args, &v, codec)?);
+        }
+        query
+    } else {
+        return Err(PyTypeError::new_err(
+            "Query parameters must be a tuple, list or dict",
+        ));
+    };
+
+    if codec.backend == Backend::Postgres && casts.iter().any(|v| *v != PgCast::Plain) {
+        return Ok((cast_placeholders(&query, &casts), args));
+    }
+    Ok((query, args))
+}
+
+/// `bind_params` for async methods, whose arguments can't borrow from python
+pub(crate) fn bind_params_owned(
+    query: &str,
+    params: Option<PyObject>,
+    codec: &Codec,
+) -> PyResult<(String, QueryArgs)> {
+    Python::with_gil(|py| bind_params(query, params.as_ref().map(|v| v.bind(py)), codec))
+}
+
+/// Binds every parameter set of `seq_of_params` against `query`
+///
+/// The (rewritten) query is per set, as postgres placeholders depend on the values (see `cast_placeholders`).
+pub(crate) fn bind_batch(
+    query: &str,
+    seq_of_params: PyObject,
+    codec: &Codec,
+) -> PyResult<Vec<(String, QueryArgs)>> {
+    Python::with_gil(|py| {
+        seq_of_params
+            .bind(py)
+            .try_iter()?
+            .map(|params| bind_params(query, Some(&params?), codec))
+            .collect()
+    })
+}
+
+#[inline]
+fn is_ident_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// End of the quoted string/identifier, comment or `::` cast starting at `i`, `None` for anything else
+///
+/// On postgres this includes dollar-quoted strings. Unterminated ones run to the end of the query.
+fn skip_non_code(bytes: &[u8], i: usize, backend: Backend) -> Option<usize> {
+    let end = match bytes[i] {
+        q @ (b'\'' | b'"') => {
+            // Doubled quotes are escapes, which this handles as two adjacent literals
+            let mut j = i + 1;
+            while j < bytes.len() && bytes[j] != q {
+                j += 1;
+            }
+            j + 1
+        }
+        b'-' if bytes.get(i + 1) == Some(&b'-') => {
+            let mut j = i;
+            while j < bytes.len() && bytes[j] != b'\n' {
+                j += 1;
+            }
+            j
+        }
+        b'/' if bytes.get(i + 1) == Some(&b'*') => {
+            let mut j = i + 2;
+            while j < bytes.len() && !(bytes[j] == b'*' && bytes.get(j + 1) == Some(&b'/')) {
+                j += 1;
+            }
+            j + 2
+        }
+        b':' if bytes.get(i + 1) == Some(&b':') => i + 2,
+        b'$' if backend == Backend::Postgres => dollar_quote_end(bytes, i)?,
+        _ => return None,
+    };
+    Some(end.min(bytes.len()))
+}
+
+/// End of a `$tag$ ... $tag$` (or `$$ ... $$`) string starting at `i`, `None` for placeholders like `$1`
+fn dollar_quote_end(bytes: &[u8], i: usize) -> Option<usize> {
+    // `$` is also allowed within identifiers
+    if i > 0 && is_ident_char(bytes[i - 1]) {
+        return None;
+    }
+    let tag_len = bytes[i + 1..].iter().position(|c| *c == b'$')?;
+    let tag = &bytes[i + 1..i + 1 + tag_len];
+    if tag.first().is_some_and(u8::is_ascii_digit) || !tag.iter().all(|c| is_ident_char(*c)) {
+        return None;
+    }
+    let open = &bytes[i..i + tag_len + 2];
+    let body = i + open.len();
+    Some(
+        bytes[body..]
+            .windows(open.len())
+            .position(|w| w == open)
+            .map_or(bytes.len(), |p| body + p + open.len()),
+    )
+}
+
+/// Rewrites `:name` placeholders into positional ones for the given backend
+///
+/// Skips over quoted strings/identifiers, comments and postgres `::` casts.
+/// Returns the rewritten query and the parameter names in bind order.
+fn rewrite_named(query: &str, backend: Backend) -> (String, Vec<String>) {
+    let bytes = query.as_bytes();
+    let mut out = String::with_capacity(query.len());
+    let mut names: Vec<String> = Vec::new();
+    let mut last = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if let Some(end) = skip_non_code(bytes, i, backend) {
+            i = end;
+            continue;
+        }
+        match bytes[i] {
+            // Names can't start with a digit, which keeps array slices (`arr[1:2]`) intact
+            b':' if bytes
+                .get(i + 1)
+                .is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_') =>
+            {
+                let start = i + 1;
+                let mut end = start;
+                while end < bytes.len() && is_ident_char(bytes[end]) {
+                    end += 1;
+                }
+                let name = &query[start..end];
+
+                out.push_str(&query[last..i]);
+                match backend {
+                    // Positional `?` consumes a new argument for every occurrence
+                    Backend::Sqlite => {
+                        out.push('?');
+                        names.push(name.to_owned());
+                    }
+                    Backend::Postgres => {
+                        let idx = match names.iter().position(|v| v == name) {
+                            Some(idx) => idx,
+                            None => {
+                                names.push(name.to_owned());
+                                names.len() - 1
+                            }
+                        };
+                        out.push('$');
+                        out.push_str(&(idx + 1).to_string());
+                    }
+                }
+                last = end;
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    out.push_str(&query[last..]);
+
+    (out, names)
+}
+
+/// Rewrites the `$n` placeholders of postgres parameters according to their `PgCast`
+fn cast_placeholders(query: &str, casts: &[PgCast]) -> String {
+    let bytes = query.as_bytes();
+    let mut out = String::with_capacity(query.len());
+    let mut last = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if let Some(end) = skip_non_code(bytes, i, Backend::Postgres) {
+            i = end;
+            continue;
+        }
+        if bytes[i] != b'$' || (i > 0 && is_ident_char(bytes[i - 1])) {
+            i += 1;
+            continue;
+        }
+        let end = i
+            + 1
+            + bytes[i + 1..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .count();
+        let cast = query[i + 1..end]
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| casts.get(n.checked_sub(1)?));
+        match cast {
+            Some(PgCast::Null) => {
+                out.push_str(&query[last..i]);
+                out.push_str("NULL");
+                last = end;
+            }
+            Some(PgCast::Type(ty)) => {
+                out.push_str(&query[last..end]);
+                out.push_str("::");
+                out.push_str(ty);
+                last = end;
+            }
+            _ => {}
+        }
+        i = end;
+    }
+    out.push_str(&query[last..]);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_named_sqlite_repeats_arguments() {
+        let (query, names) = rewrite_named(
+            "SELECT * FROM t WHERE a = :a AND b = :b OR a = :a",
+            Backend::Sqlite,
+        );
+        assert_eq!(query, "SELECT * FROM t WHERE a = ? AND b = ? OR a = ?");
+        assert_eq!(names, ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn rewrite_named_postgres_reuses_positions() {
+        let (query, names) = rewrite_named(
+            "SELECT * FROM t WHERE a = :a AND b = :b_2 OR a = :a",
+            Backend::Postgres,
+        );
+        assert_eq!(query, "SELECT * FROM t WHERE a = $1 AND b = $2 OR a = $1");
+        assert_eq!(names, ["a", "b_2"]);
+    }
+
+    #[test]
+    fn rewrite_named_skips_quotes_and_comments() {
+        let query = "SELECT ':a', \"x:b\", 'it''s :c' -- :d\n/* :e */ FROM t WHERE a = :f";
+        let (rewritten, names) = rewrite_named(query, Backend::Sqlite);
+        assert_eq!(
+            rewritten,
+            "SELECT ':a', \"x:b\", 'it''s :c' -- :d\n/* :e */ FROM t WHERE a = ?"
+        );
+        assert_eq!(names, ["f"]);
+    }
+
+    #[test]
+    fn rewrite_named_skips_casts_and_slices() {
+        let (query, names) = rewrite_named(
+            "SELECT :a::int, arr[1:2], x::text FROM t",
+            Backend::Postgres,
+        );
+        assert_eq!(query, "SELECT $1::int, arr[1:2], x::text FROM t");
+        assert_eq!(names, ["a"]);
+    }
+
+    #[test]
+    fn rewrite_named_unterminated() {
+        let (query, names) = rewrite_named("SELECT :a, 'open :b", Backend::Sqlite);
+        assert_eq!(query, "SELECT ?, 'open :b");
+        assert_eq!(names, ["a"]);
+
+        let (query, names) = rewrite_named("SELECT :a /* open :b", Backend::Sqlite);
+        assert_eq!(query, "SELECT ? /* open :b");
+        assert_eq!(names, ["a"]);
+
+        let (query, names) = rewrite_named("SELECT 1:", Backend::Sqlite);
+        assert_eq!(query, "SELECT 1:");
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn rewrite_named_skips_dollar_quotes() {
+        let (query, names) =
+            rewrite_named("SELECT $$ :a $$, $x$ :b $$ $x$, a$b, :c", Backend::Postgres);
+        assert_eq!(query, "SELECT $$ :a $$, $x$ :b $$ $x$, a$b, $1");
+        assert_eq!(names, ["c"]);
+    }
+
+    #[test]
+    fn cast_placeholders_replaces_nulls() {
+        let casts = [PgCast::Plain, PgCast::Null];
+        assert_eq!(
+            cast_placeholders("SELECT $1, $2, $2::bytea, '$2', $$ $2 $$, $12", &casts),
+            "SELECT $1, NULL, NULL::bytea, '$2', $$ $2 $$, $12"
+        );
+    }
+
+    #[test]
+    fn cast_placeholders_casts_types() {
+        let casts = [PgCast::Type("DATE"), PgCast::Plain];
+        assert_eq!(
+            cast_placeholders("SELECT $1, $2, $1::text", &casts),
+            "SELECT $1::DATE, $2, $1::DATE::text"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
+};
+
+use pyo3::prelude::*;
+
+/// Wakes in progress on runtime threads, see `wait_for_wakers`
+static WAKING: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts the wake of the python coroutine's waker while it is in progress
+struct TrackedWaker(Waker);
+
+impl Wake for TrackedWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        WAKING.fetch_add(1, Ordering::SeqCst);
+        self.0.wake_by_ref();
+        WAKING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A future polled with a `TrackedWaker`
+struct Tracked<F>(F);
+
+impl<F: Future + Unpin> Future for Tracked<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let waker = Waker::from(Arc::new(TrackedWaker(cx.waker().clone())));
+        Pin::new(&mut self.0).poll(&mut Context::from_waker(&waker))
+    }
+}
+
+/// Runs a sqlx future on the async runtime and awaits its output
+///
+/// Polling sqlx futures directly from a python coroutine can deadlock: driver threads may fire
+/// wakers while holding internal locks, and the pyo3 waker needs the GIL which the polling thread holds.
+/// The `JoinHandle` of a spawned task is woken without holding any such locks.
+pub(crate) async fn spawn<F, T>(fut: F) -> T
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    Tracked(async_std::task::spawn(fut)).await
+}
+
+/// Blocks (without the GIL) until no runtime thread is waking a python coroutine, registered with `atexit`
+///
+/// A task may finish (and its coroutine resume) before the runtime thread is done waking it, which
+/// takes the GIL. If the interpreter finalizes in between, that thread crashes the process.
+#[pyfunction]
+pub(crate) fn wait_for_wakers(py: Python<'_>) {
+    py.allow_threads(|| {
+        while WAKING.load(Ordering::SeqCst) > 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    })
+}
@@ -0,0 +1,244 @@
+use std::borrow::Cow;
+
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    intern,
+    prelude::*,
+    types::{timezone_utc, PyDict, PyString, PyType},
+};
+
+use crate::{
+    codec::Codec,
+    typeref::{DATETIME_TYPE, DATE_TYPE, TIME_TYPE, UTCOFFSET_METHOD_STR, ZONEINFO_TYPE},
+    Backend,
+};
+
+/// `datetime`, `date` or `time`, stored as ISO 8601 text on sqlite and natively on postgres
+///
+/// Datetimes are `timestamptz` on postgres, so they're always exchanged with an offset (see `NaiveDatetime`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Temporal {
+    DateTime,
+    Date,
+    Time,
+}
+
+impl Temporal {
+    /// `datetime` is a subclass of `date`, so it's checked first
+    pub(crate) fn of_type(ptype: &Bound<'_, PyType>) -> Option<Self> {
+        let ptr = ptype.as_type_ptr();
+        // SAFETY: the typerefs are initialized at module init and live as long as the interpreter
+        unsafe {
+            if pyo3::ffi::PyType_IsSubtype(ptr, DATETIME_TYPE) != 0 {
+                Some(Temporal::DateTime)
+            } else if pyo3::ffi::PyType_IsSubtype(ptr, DATE_TYPE) != 0 {
+                Some(Temporal::Date)
+            } else if pyo3::ffi::PyType_IsSubtype(ptr, TIME_TYPE) != 0 {
+                Some(Temporal::Time)
+            } else {
+                None
+            }
+        }
+    }
+
+    fn py_type(self, py: Python<'_>) -> Bound<'_, PyType> {
+        let ptr = match self {
+            Temporal::DateTime => unsafe { DATETIME_TYPE },
+            Temporal::Date => unsafe { DATE_TYPE },
+            Temporal::Time => unsafe { TIME_TYPE },
+        };
+        // SAFETY: see `of_type`
+        unsafe { Bound::from_borrowed_ptr(py, ptr.cast()).downcast_into_unchecked() }
+    }
+
+    /// Native postgres type
+    pub(crate) fn pg_type(self) -> &'static str {
+        match self {
+            Temporal::DateTime => "TIMESTAMPTZ",
+            Temporal::Date => "DATE",
+            Temporal::Time => "TIME",
+        }
+    }
+
+    /// Parses ISO 8601 text, as written by `to_iso`, sqlite's `CURRENT_TIMESTAMP` or postgres' text output
+    ///
+    /// Naive datetimes get the timezone assumed by `naive`, unless naive values are rejected.
+    pub(crate) fn parse(
+        self,
+        text: &Bound<'_, PyAny>,
+        naive: &NaiveDatetime,
+    ) -> PyResult<PyObject> {
+        let py = text.py();
+        let text = text.downcast::<PyString>()?.to_str()?;
+        let value = self
+            .py_type(py)
+            .call_method1(intern!(py, "fromisoformat"), (normalize_iso(text),))?;
+        if self == Temporal::DateTime && !is_aware(&value)? {
+            if let Some(value) = naive.assume(&value)? {
+                return Ok(value.unbind());
+            }
+        }
+        Ok(value.unbind())
+    }
+}
+
+const NAIVE_EXPECTED: &str =
+    "expected \"reject\", \"utc\", \"local\", a datetime.timezone or a zoneinfo.ZoneInfo";
+
+/// How naive datetimes are interpreted, see `SqlxDb(naive_datetime=...)`
+pub(crate) enum NaiveDatetime {
+    /// Raises when writing, values read without an offset stay naive
+    Reject,
+    Utc,
+    /// The system's local timezone
+    Local,
+    /// A `datetime.timezone` or `zoneinfo.ZoneInfo`
+    Zone(PyObject),
+}
+
+impl NaiveDatetime {
+    /// `"reject"`, `"utc"`, `"local"` or a timezone
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let py = value.py();
+        if let Ok(name) = value.downcast::<PyString>() {
+            return match name.to_str()? {
+                "reject" => Ok(NaiveDatetime::Reject),
+                "utc" => Ok(NaiveDatetime::Utc),
+                "local" => Ok(NaiveDatetime::Local),
+                other => Err(PyValueError::new_err(format!(
+                    "Unknown naive_datetime {other:?}, {NAIVE_EXPECTED}"
+                ))),
+            };
+        }
+
+        // pytz zones aren't accepted, attaching them with `replace` yields their LMT offset
+        let is_timezone = value.get_type().is(&timezone_utc(py).get_type());
+        // SAFETY: initialized at module init, null if there's no `zoneinfo`
+        let is_zoneinfo = unsafe {
+            !ZONEINFO_TYPE.is_null()
+                && pyo3::ffi::PyObject_TypeCheck(value.as_ptr(), ZONEINFO_TYPE) != 0
+        };
+        if !is_timezone && !is_zoneinfo {
+            return Err(PyTypeError::new_err(format!(
+                "Invalid naive_datetime {value}, {NAIVE_EXPECTED}"
+            )));
+        }
+        Ok(NaiveDatetime::Zone(value.clone().unbind()))
+    }
+
+    /// `value` with the assumed timezone attached, `None` if naive values are rejected
+    fn assume<'py>(&self, value: &Bound<'py, PyAny>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        let py = value.py();
+        let with_tzinfo = |tzinfo: &Bound<'py, PyAny>| {
+            let kwargs = PyDict::new(py);
+            kwargs.set_item(intern!(py, "tzinfo"), tzinfo)?;
+            value.call_method(intern!(py, "replace"), (), Some(&kwargs))
+        };
+        Ok(Some(match self {
+            NaiveDatetime::Reject => return Ok(None),
+            NaiveDatetime::Utc => with_tzinfo(timezone_utc(py).as_any())?,
+            // `astimezone` takes naive values as local time
+            NaiveDatetime::Local => value.call_method0(intern!(py, "astimezone"))?,
+            NaiveDatetime::Zone(tzinfo) => with_tzinfo(tzinfo.bind(py))?,
+        }))
+    }
+}
+
+/// Whether a datetime has an offset, which a `tzinfo` may also leave undefined
+fn is_aware(value: &Bound<'_, PyAny>) -> PyResult<bool> {
+    // SAFETY: interned at module init
+    let utcoffset = unsafe {
+        Bound::from_borrowed_ptr(value.py(), UTCOFFSET_METHOD_STR)
+            .downcast_into_unchecked::<PyString>()
+    };
+    Ok(!value.call_method0(utcoffset)?.is_none())
+}
+
+/// ISO 8601 text of a `datetime`, `date` or `time`
+///
+/// Naive datetimes get the timezone assumed by `codec.naive_datetime`. Datetimes are normalized to UTC for
+/// postgres' `timestamptz`, while sqlite keeps their offset.
+pub(crate) fn to_iso(value: &Bound<'_, PyAny>, codec: &Codec) -> PyResult<String> {
+    let py = value.py();
+    let mut value = value.clone();
+    if Temporal::of_type(&value.get_type()) == Some(Temporal::DateTime) {
+        if !is_aware(&value)? {
+            value = codec.naive_datetime.assume(&value)?.ok_or_else(|| {
+                PyValueError::new_err(format!(
+                    "Naive datetime {value} is not allowed with naive_datetime=\"reject\""
+                ))
+            })?;
+        }
+        if codec.backend == Backend::Postgres {
+            value = value.call_method1(intern!(py, "astimezone"), (timezone_utc(py),))?;
+        }
+    }
+    value.call_method0(intern!(py, "isoformat"))?.extract()
+}
+
+/// Makes postgres' text output acceptable to `fromisoformat` before python 3.11
+///
+/// Pads (or truncates) fractional seconds to 6 digits, as only 3 or 6 are accepted while postgres trims
+/// trailing zeros, and expands offsets of whole hours (`+02`).
+fn normalize_iso(text: &str) -> Cow<'_, str> {
+    let mut text = Cow::Borrowed(text);
+    if let Some(dot) = text.rfind('.') {
+        let start = dot + 1;
+        let digits = text[start..].bytes().take_while(u8::is_ascii_digit).count();
+        if digits != 0 && digits != 6 {
+            let end = start + digits;
+            text = Cow::Owned(format!(
+                "{}{:0<6}{}",
+                &text[..start],
+                &text[start..end.min(start + 6)],
+                &text[end..]
+            ));
+        }
+    }
+
+    let bytes = text.as_bytes();
+    let len = bytes.len();
+    // Only times have an offset, `:` rules out the day of a date
+    if len >= 3
+        && text.contains(':')
+        && matches!(bytes[len - 3], b'+' | b'-')
+        && bytes[len - 2..].iter().all(u8::is_ascii_digit)
+    {
+        text.to_mut().push_str(":00");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_iso_fractions() {
+        assert_eq!(
+            normalize_iso("2024-01-02 10:00:00.5"),
+            "2024-01-02 10:00:00.500000"
+        );
+        assert_eq!(normalize_iso("10:00:00.1234"), "10:00:00.123400");
+        assert_eq!(normalize_iso("10:00:00.123456789"), "10:00:00.123456");
+        assert!(matches!(
+            normalize_iso("2024-01-02T10:00:00.123456"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn normalize_iso_offsets() {
+        assert_eq!(
+            normalize_iso("2024-01-02 10:00:00+02"),
+            "2024-01-02 10:00:00+02:00"
+        );
+        assert_eq!(normalize_iso("10:00:00.25-05"), "10:00:00.250000-05:00");
+        assert_eq!(
+            normalize_iso("2024-01-02 10:00:00+05:30"),
+            "2024-01-02 10:00:00+05:30"
+        );
+        // The day of a date isn't an offset
+        assert_eq!(normalize_iso("2024-01-02"), "2024-01-02");
+    }
+}
@@ -0,0 +1,346 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Weak,
+};
+
+use futures::lock::{Mutex, OwnedMutexGuard};
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyString};
+use sqlx::{AnyConnection, AnyPool, Executor, Transaction};
+
+use crate::{
+    codec::Codec,
+    error::{to_pyerr, InterfaceError},
+    params::{bind_batch, bind_params, bind_params_owned},
+    rt, Backend, RowStream, SqlxQueryResult, SqlxRow, SqlxStreamRequest,
+};
+
+/// Connection of a transaction, `None` once the root transaction was committed or rolled back
+pub(crate) type TxState = Option<Transaction<'static, sqlx::Any>>;
+
+const ISOLATION_LEVELS: [&str; 4] = [
+    "read uncommitted",
+    "read committed",
+    "repeatable read",
+    "serializable",
+];
+
+fn savepoint_name(depth: usize) -> String {
+    format!("pysqlx_sp_{depth}")
+}
+
+#[inline]
+fn as_conn(state: &mut TxState) -> &mut AnyConnection {
+    state
+        .as_mut()
+        .expect("Transaction should be checked to be active")
+}
+
+/// A stream with the depth of the (nested) transaction that started it
+type DepthStream = (usize, Weak<RowStream>);
+
+/// Streams started on a transaction's connection
+#[derive(Clone, Default)]
+struct OpenStreams(Arc<std::sync::Mutex<Vec<DepthStream>>>);
+
+impl OpenStreams {
+    fn push(&self, depth: usize, stream: &SqlxStreamRequest) {
+        let mut streams = self.0.lock().unwrap();
+        streams.retain(|(_, v)| v.upgrade().is_some_and(|v| v.is_open()));
+        streams.push((depth, Arc::downgrade(&stream.inner)));
+    }
+
+    fn any_open(&self) -> bool {
+        let streams = self.0.lock().unwrap();
+        streams
+            .iter()
+            .any(|(_, v)| v.upgrade().is_some_and(|v| v.is_open()))
+    }
+
+    /// Closes the streams started at `depth` or deeper, which unlocks the connection unless an outer stream holds it
+    async fn close_from(&self, depth: usize) {
+        let streams: Vec<Arc<RowStream>> = {
+            let mut streams = self.0.lock().unwrap();
+            let (closing, kept) = streams.drain(..).partition(|(d, _)| *d >= depth);
+            *streams = kept;
+            closing
+                .into_iter()
+                .filter_map(|(_, v)| v.upgrade())
+                .collect()
+        };
+        rt::spawn(async move {
+            for stream in streams {
+                stream.close().await;
+            }
+        })
+        .await;
+    }
+}
+
+/// A transaction, or a savepoint within one when created through `SqlxTransaction.begin`
+///
+/// All (nested) transactions share one connection; operations lock it for their duration (queueing
+/// behind each other), and streams lock it until they are exhausted, closed or the transaction exits.
+#[pyclass]
+pub(crate) struct SqlxTransaction {
+    state: Arc<Mutex<TxState>>,
+    codec: Arc<Codec>,
+    /// 0 for the root transaction, the savepoint depth otherwise
+    depth: usize,
+    finished: AtomicBool,
+    streams: OpenStreams,
+}
+
+impl SqlxTransaction {
+    pub(crate) async fn start(
+        pool: AnyPool,
+        codec: Arc<Codec>,
+        isolation_level: Option<String>,
+    ) -> PyResult<Self> {
+        let isolation_level = match isolation_level {
+            None => None,
+            Some(_) if codec.backend != Backend::Postgres => {
+                return Err(PyValueError::new_err(
+                    "isolation_level is only supported on postgres",
+                ))
+            }
+            Some(level) => {
+                let level = level.to_ascii_lowercase().replace('_', " ");
+                if !ISOLATION_LEVELS.contains(&level.as_str()) {
+                    return Err(PyValueError::new_err(format!(
+                        "Unknown isolation level {level:?}, expected one of {ISOLATION_LEVELS:?}"
+                    )));
+                }
+                Some(level)
+            }
+        };
+
+        let tx = rt::spawn(async move {
+            let mut tx = pool.begin().await?;
+            if let Some(level) = isolation_level {
+                // Must be the first statement of the transaction
+                tx.execute(format!("SET TRANSACTION ISOLATION LEVEL {level}").as_str())
+                    .await?;
+            }
+            Ok::<_, sqlx::Error>(tx)
+        })
+        .await
+        .map_err(to_pyerr)?;
+
+        Ok(SqlxTransaction {
+            state: Arc::new(Mutex::new(Some(tx))),
+            codec,
+            depth: 0,
+            finished: AtomicBool::new(false),
+            streams: OpenStreams::default(),
+        })
+    }
+
+    fn check_active(&self, state: &TxState) -> PyResult<()> {
+        if self.finished.load(Ordering::Acquire) || state.is_none() {
+            return Err(InterfaceError::new_err(
+                "Transaction was already committed or rolled back",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Locks the connection for a single operation, waiting for other operations to finish
+    ///
+    /// Fails if an open stream holds the connection, since waiting for it would usually block forever.
+    async fn acquire(&self) -> PyResult<OwnedMutexGuard<TxState>> {
+        let guard = match self.state.clone().try_lock_owned() {
+            Some(guard) => guard,
+            None if self.streams.any_open() => {
+                return Err(InterfaceError::new_err(
+                    "Transaction is in use by an open stream",
+                ))
+            }
+            // Awaited on the runtime, like sqlx futures (see `rt::spawn`)
+            None => rt::spawn(self.state.clone().lock_owned()).await,
+        };
+        self.check_active(&guard)?;
+        Ok(guard)
+    }
+
+    async fn finish(&self, commit: bool) -> PyResult<()> {
+        let mut guard = self.acquire().await?;
+        self.finished.store(true, Ordering::Release);
+        let depth = self.depth;
+
+        rt::spawn(async move {
+            if depth == 0 {
+                let tx = guard
+                    .take()
+                    .expect("Transaction should be checked to be active");
+                return if commit {
+                    tx.commit().await
+                } else {
+                    tx.rollback().await
+                };
+            }
+
+            let conn = as_conn(&mut guard);
+            let savepoint = savepoint_name(depth);
+            if !commit {
+                conn.execute(format!("ROLLBACK TO SAVEPOINT {savepoint}").as_str())
+                    .await?;
+            }
+            conn.execute(format!("RELEASE SAVEPOINT {savepoint}").as_str())
+                .await?;
+            Ok(())
+        })
+        .await
+        .map_err(to_pyerr)
+    }
+}
+
+#[pymethods]
+impl SqlxTransaction {
+    /// Starts a nested transaction using a savepoint
+    async fn begin(&self) -> PyResult<SqlxTransaction> {
+        let mut guard = self.acquire().await?;
+        let depth = self.depth + 1;
+
+        rt::spawn(async move {
+            as_conn(&mut guard)
+                .execute(format!("SAVEPOINT {}", savepoint_name(depth)).as_str())
+                .await
+        })
+        .await
+        .map_err(to_pyerr)?;
+
+        Ok(SqlxTransaction {
+            state: self.state.clone(),
+            codec: self.codec.clone(),
+            depth,
+            finished: AtomicBool::new(false),
+            streams: self.streams.clone(),
+        })
+    }
+
+    async fn commit(&self) -> PyResult<()> {
+        self.finish(true).await
+    }
+
+    async fn rollback(&self) -> PyResult<()> {
+        self.finish(false).await
+    }
+
+    #[pyo3(signature = (query, params=None))]
+    fn start_query<'py>(
+        &self,
+        query: Bound<'py, PyAny>,
+        params: Option<Bound<'py, PyAny>>,
+    ) -> PyResult<SqlxStreamRequest> {
+        let query = query.downcast_into_exact::<PyString>()?;
+        let (query, args) = bind_params(query.to_str()?, params.as_ref(), &self.codec)?;
+        // Streams are started right away, so they can't wait for the connection
+        let guard = self.state.clone().try_lock_owned().ok_or_else(|| {
+            InterfaceError::new_err("Transaction is in use by another operation or an open stream")
+        })?;
+        self.check_active(&guard)?;
+
+        let stream = SqlxStreamRequest::on_transaction(query, args, guard);
+        self.streams.push(self.depth, &stream);
+        Ok(stream)
+    }
+
+    #[pyo3(signature = (query, params=None))]
+    async fn execute(&self, query: String, params: Option<PyObject>) -> PyResult<SqlxQueryResult> {
+        let (query, args) = bind_params_owned(&query, params, &self.codec)?;
+        let (mut guard, backend) = (self.acquire().await?, self.codec.backend);
+
+        rt::spawn(async move {
+            SqlxQueryResult::execute(as_conn(&mut guard), backend, &query, args).await
+        })
+        .await
+        .map_err(to_pyerr)
+    }
+
+    /// Executes `query` once for every parameter set in `seq_of_params`
+    async fn execute_many(
+        &self,
+        query: String,
+        seq_of_params: PyObject,
+    ) -> PyResult<SqlxQueryResult> {
+        let batch = bind_batch(&query, seq_of_params, &self.codec)?;
+        let (mut guard, backend) = (self.acquire().await?, self.codec.backend);
+
+        rt::spawn(async move {
+            SqlxQueryResult::execute_batch(as_conn(&mut guard), backend, batch).await
+        })
+        .await
+        .map_err(to_pyerr)
+    }
+
+    #[pyo3(signature = (query, params=None))]
+    async fn fetch_one(&self, query: String, params: Option<PyObject>) -> PyResult<SqlxRow> {
+        let (query, args) = bind_params_owned(&query, params, &self.codec)?;
+        let mut guard = self.acquire().await?;
+
+        let row = rt::spawn(async move {
+            as_conn(&mut guard)
+                .fetch_one(sqlx::query_with(&query, args))
+                .await
+        })
+        .await
+        .map_err(to_pyerr)?;
+        Ok(SqlxRow(row))
+    }
+
+    #[pyo3(signature = (query, params=None))]
+    async fn fetch_optional(
+        &self,
+        query: String,
+        params: Option<PyObject>,
+    ) -> PyResult<Option<SqlxRow>> {
+        let (query, args) = bind_params_owned(&query, params, &self.codec)?;
+        let mut guard = self.acquire().await?;
+
+        let row = rt::spawn(async move {
+            as_conn(&mut guard)
+                .fetch_optional(sqlx::query_with(&query, args))
+                .await
+        })
+        .await
+        .map_err(to_pyerr)?;
+        Ok(row.map(SqlxRow))
+    }
+
+    #[pyo3(signature = (query, params=None))]
+    async fn fetch_all(&self, query: String, params: Option<PyObject>) -> PyResult<Vec<SqlxRow>> {
+        let (query, args) = bind_params_owned(&query, params, &self.codec)?;
+        let mut guard = self.acquire().await?;
+
+        let rows = rt::spawn(async move {
+            as_conn(&mut guard)
+                .fetch_all(sqlx::query_with(&query, args))
+                .await
+        })
+        .await
+        .map_err(to_pyerr)?;
+        Ok(rows.into_iter().map(SqlxRow).collect())
+    }
+
+    async fn __aenter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    /// Commits on a clean exit and rolls back when an exception was raised,
+    /// unless the transaction was already finished explicitly
+    ///
+    /// Streams started within the transaction (or its savepoints) that are still open are closed first.
+    async fn __aexit__(
+        &self,
+        exc_type: PyObject,
+        _exc_value: PyObject,
+        _traceback: PyObject,
+    ) -> PyResult<bool> {
+        if !self.finished.load(Ordering::Acquire) {
+            self.streams.close_from(self.depth).await;
+            let commit = Python::with_gil(|py| exc_type.is_none(py));
+            self.finish(commit).await?;
+        }
+        Ok(false)
+    }
+}
@@ -1,5 +1,5 @@
 use pyo3::ffi::{PyObject, PyTypeObject};
-use std::ptr::null_mut;
+use std::{ffi::CStr, ptr::null_mut};
 
 pub static mut DEFAULT: *mut PyObject = null_mut();
 pub static mut OPTION: *mut PyObject = null_mut();
@@ -24,13 +24,14 @@ pub static mut DATE_TYPE: *mut PyTypeObject = null_mut();
 pub static mut TIME_TYPE: *mut PyTypeObject = null_mut();
 pub static mut TUPLE_TYPE: *mut PyTypeObject = null_mut();
 pub static mut UUID_TYPE: *mut PyTypeObject = null_mut();
+pub static mut DECIMAL_TYPE: *mut PyTypeObject = null_mut();
 pub static mut ENUM_TYPE: *mut PyTypeObject = null_mut();
 pub static mut FIELD_TYPE: *mut PyTypeObject = null_mut();
 pub static mut FRAGMENT_TYPE: *mut PyTypeObject = null_mut();
 
 // pub static mut NUMPY_TYPES: OnceBox<Option<NonNull<NumpyTypes>>> = OnceBox::new();
 
-#[cfg(Py_3_9)]
+/// Null before python 3.9, which has no `zoneinfo`
 pub static mut ZONEINFO_TYPE: *mut PyTypeObject = null_mut();
 
 pub static mut UTCOFFSET_METHOD_STR: *mut PyObject = null_mut();
@@ -50,10 +51,70 @@ pub static mut INT_ATTR_STR: *mut PyObject = null_mut();
 
 static INIT: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
 
+#[cold]
+unsafe fn datetime_api() -> *mut pyo3::ffi::PyDateTime_CAPI {
+    // Only imports once
+    pyo3::ffi::PyDateTime_IMPORT();
+    pyo3::ffi::PyDateTimeAPI()
+}
+
+#[cold]
+unsafe fn look_up_datetime_type() -> *mut PyTypeObject {
+    (*datetime_api()).DateTimeType
+}
+
+#[cold]
+unsafe fn look_up_date_type() -> *mut PyTypeObject {
+    (*datetime_api()).DateType
+}
+
+#[cold]
+unsafe fn look_up_time_type() -> *mut PyTypeObject {
+    (*datetime_api()).TimeType
+}
+
+/// `module.name`, null with the python error set if it can't be imported
+#[cold]
+unsafe fn look_up_type(module: &CStr, name: &CStr) -> *mut PyTypeObject {
+    let module = pyo3::ffi::PyImport_ImportModule(module.as_ptr());
+    if module.is_null() {
+        return null_mut();
+    }
+    // The reference is kept, like the type it lives as long as the interpreter
+    let ptr = pyo3::ffi::PyObject_GetAttrString(module, name.as_ptr());
+    pyo3::ffi::Py_DECREF(module);
+    ptr as *mut PyTypeObject
+}
+
+#[cold]
+unsafe fn look_up_uuid_type() -> *mut PyTypeObject {
+    look_up_type(c"uuid", c"UUID")
+}
+
+#[cold]
+unsafe fn look_up_decimal_type() -> *mut PyTypeObject {
+    look_up_type(c"decimal", c"Decimal")
+}
+
+#[cold]
+unsafe fn look_up_enum_type() -> *mut PyTypeObject {
+    look_up_type(c"enum", c"Enum")
+}
+
+#[cold]
+unsafe fn look_up_zoneinfo_type() -> *mut PyTypeObject {
+    let ptr = look_up_type(c"zoneinfo", c"ZoneInfo");
+    if ptr.is_null() {
+        pyo3::ffi::PyErr_Clear();
+    }
+    ptr
+}
+
 #[cold]
 #[cfg_attr(feature = "optimize", optimize(size))]
-pub fn init_typerefs() {
-    INIT.get_or_init(_init_typerefs_impl);
+/// False with the python error set if a required type can't be looked up
+pub fn init_typerefs() -> bool {
+    *INIT.get_or_init(_init_typerefs_impl)
 }
 
 #[cold]
@@ -91,20 +152,31 @@ fn _init_typerefs_impl() -> bool {
         // BOOL_TYPE = (*TRUE).ob_type;
         // INT_TYPE = (*PyLong_FromLongLong(0)).ob_type;
         // FLOAT_TYPE = (*PyFloat_FromDouble(0.0)).ob_type;
-        // DATETIME_TYPE = look_up_datetime_type();
-        // DATE_TYPE = look_up_date_type();
-        // TIME_TYPE = look_up_time_type();
-        // UUID_TYPE = look_up_uuid_type();
-        // ENUM_TYPE = look_up_enum_type();
+        if datetime_api().is_null() {
+            return false;
+        }
+        DATETIME_TYPE = look_up_datetime_type();
+        DATE_TYPE = look_up_date_type();
+        TIME_TYPE = look_up_time_type();
+        // These are used without null checks, so the module fails to import without them
+        UUID_TYPE = look_up_uuid_type();
+        if UUID_TYPE.is_null() {
+            return false;
+        }
+        DECIMAL_TYPE = look_up_decimal_type();
+        if DECIMAL_TYPE.is_null() {
+            return false;
+        }
+        ENUM_TYPE = look_up_enum_type();
+        if ENUM_TYPE.is_null() {
+            return false;
+        }
         // FIELD_TYPE = look_up_field_type();
 
-        // #[cfg(Py_3_9)]
-        // {
-        //     ZONEINFO_TYPE = look_up_zoneinfo_type();
-        // }
+        ZONEINFO_TYPE = look_up_zoneinfo_type();
 
         // INT_ATTR_STR = PyUnicode_InternFromString("int\0".as_ptr() as *const c_char);
-        // UTCOFFSET_METHOD_STR = PyUnicode_InternFromString("utcoffset\0".as_ptr() as *const c_char);
+        UTCOFFSET_METHOD_STR = pyo3::ffi::PyUnicode_InternFromString(c"utcoffset".as_ptr());
         // NORMALIZE_METHOD_STR = PyUnicode_InternFromString("normalize\0".as_ptr() as *const c_char);
         // CONVERT_METHOD_STR = PyUnicode_InternFromString("convert\0".as_ptr() as *const c_char);
         // DST_STR = PyUnicode_InternFromString("dst\0".as_ptr() as *const c_char);
@@ -0,0 +1,53 @@
+use pyo3::{
+    exceptions::PyValueError,
+    intern,
+    prelude::*,
+    types::{PyBytes, PyDict, PyType},
+};
+
+use crate::typeref::UUID_TYPE;
+
+/// Storage of UUIDs on sqlite, postgres always uses its native `uuid`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UuidFormat {
+    /// 16 byte BLOB
+    Bytes,
+    /// Canonical (hyphenated) TEXT
+    Text,
+}
+
+impl UuidFormat {
+    pub(crate) fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "bytes" => Ok(UuidFormat::Bytes),
+            "text" => Ok(UuidFormat::Text),
+            other => Err(PyValueError::new_err(format!(
+                "Unknown uuid_format {other:?}, expected \"bytes\" or \"text\""
+            ))),
+        }
+    }
+}
+
+pub(crate) fn uuid_type(py: Python<'_>) -> Bound<'_, PyType> {
+    // SAFETY: initialized at module init and lives as long as the interpreter
+    unsafe { Bound::from_borrowed_ptr(py, UUID_TYPE.cast()).downcast_into_unchecked() }
+}
+
+pub(crate) fn is_uuid_type(ptype: &Bound<'_, PyType>) -> bool {
+    // SAFETY: see `uuid_type`
+    unsafe { pyo3::ffi::PyType_IsSubtype(ptype.as_type_ptr(), UUID_TYPE) != 0 }
+}
+
+/// `uuid.UUID` of its 16 bytes or its text
+pub(crate) fn parse(value: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+    let py = value.py();
+    let uuid = match value.is_instance_of::<PyBytes>() {
+        true => {
+            let kwargs = PyDict::new(py);
+            kwargs.set_item(intern!(py, "bytes"), value)?;
+            uuid_type(py).call((), Some(&kwargs))?
+        }
+        false => uuid_type(py).call1((value,))?,
+    };
+    Ok(uuid.unbind())
+}
@@ -1,4 +1,11 @@
-from typing import Annotated
+import asyncio
+import datetime
+import decimal
+import enum
+import os
+import tempfile
+import uuid
+from typing import Annotated, Optional
 from msgspec import Meta, Struct, field
 import pysqlx
 
@@ -9,10 +16,428 @@ class ExampleModel(Struct):
     c: Annotated[bytes, Meta(extra={'index': True})]
 
 
-db = pysqlx.SqlxDb('sqlite:////tmp/data.db')
+class Color(enum.Enum):
+    RED = 'red'
+    GREEN = 'green'
 
-for k, v in ExampleModel.__annotations__.items():
-    print(k, dir(v))
 
-print()
-db.register_model(ExampleModel)
+class Item(Struct):
+    id: int
+    data: dict[str, int]
+    at: datetime.datetime
+    key: uuid.UUID
+    price: Annotated[decimal.Decimal, Meta(extra={'precision': 10, 'scale': 2})]
+    color: Color
+
+
+class AccountV2(Struct):
+    __tablename__ = 'account'
+
+    id: int
+    name: str
+    email: str
+
+
+class Measure(Struct):
+    id: int
+    value: int | float
+
+
+class Product(Struct):
+    id: Annotated[int, Meta(extra={'primary_key': True})]
+    name: Annotated[str, Meta(extra={'unique': True})]
+    tags: list[str]
+    note: Optional[str] = None
+
+
+def fields(instance):
+    return tuple(getattr(instance, name) for name in instance.__struct_fields__)
+
+
+class Note(Struct):
+    id: Annotated[int, Meta(extra={'primary_key': True})]
+    text: str
+
+
+class NoteV2(Struct):
+    __tablename__ = 'note'
+
+    id: Annotated[int, Meta(extra={'primary_key': True})]
+    text: str
+    author: Annotated[Optional[str], Meta(extra={'index': True})] = None
+
+
+# e.g. postgres://postgres@localhost/postgres, the tests also run against it when set
+POSTGRES_URL = os.environ.get('PYSQLX_TEST_POSTGRES')
+
+
+async def test_params(db, pg):
+    await db.execute('DROP TABLE IF EXISTS t_params')
+    await db.execute('CREATE TABLE t_params (a INTEGER, s TEXT)')
+    if pg:
+        await db.execute('INSERT INTO t_params VALUES ($1, $2)', (1, 'x'))
+    else:
+        await db.execute('INSERT INTO t_params VALUES (?, ?)', [1, 'x'])
+    await db.execute('INSERT INTO t_params VALUES (:a, :s)', {'a': 2, 's': 'it\'s :s'})
+
+    rows = await db.fetch_all('SELECT a, s FROM t_params WHERE s <> \':a\' ORDER BY a')
+    assert [(r['a'], r['s']) for r in rows] == [(1, 'x'), (2, 'it\'s :s')]
+
+
+async def test_null_params(db, pg):
+    await db.execute('DROP TABLE IF EXISTS t_null')
+    await db.execute('CREATE TABLE t_null (s TEXT, b BYTEA, f DOUBLE PRECISION)')
+    params = {'s': None, 'b': None, 'f': None}
+    await db.execute('INSERT INTO t_null VALUES (:s, :b, :f)', params)
+    await db.execute_many('INSERT INTO t_null VALUES (:s, :b, :f)', [params, {**params, 's': 'x'}])
+
+    rows = await db.fetch_all('SELECT s, b, f FROM t_null WHERE s = :s OR s IS NULL', {'s': None})
+    assert [(r['s'], r['b'], r['f']) for r in rows] == [(None, None, None)] * 2
+
+
+async def test_sync_methods_during_await(db, pg):
+    # A pending async method keeps the db borrowed
+    slow = 'WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300000) SELECT count(*) FROM n'
+    pending = asyncio.ensure_future(db.execute(slow))
+    await asyncio.sleep(0)
+    assert not pending.done()
+
+    db.register_model(ExampleModel)
+    stream = db.start_query('SELECT 1 AS a')
+    assert (await stream.next())['a'] == 1
+    await stream.close()
+    await pending
+
+
+async def test_close_pending_stream(db, pg):
+    stream = db.start_query('SELECT 1 AS a UNION ALL SELECT 2')
+    pending = asyncio.ensure_future(stream.next())
+    await asyncio.sleep(0)
+    await stream.close()
+    assert (await pending)['a'] == 1
+    try:
+        await stream.next()
+    except pysqlx.InterfaceError:
+        pass
+    else:
+        raise AssertionError('Expected InterfaceError')
+
+    async with db.start_query('SELECT 1 AS a') as stream:
+        assert [r['a'] async for r in stream] == [1]
+
+
+async def test_model_query(db, pg):
+    await db.execute('DROP TABLE IF EXISTS item')
+    if pg:
+        await db.execute('DROP TYPE IF EXISTS item_color')
+    db.register_model(Item)
+    await db.create_table(Item)
+    item = Item(
+        id=1,
+        data={'a': 1},
+        at=datetime.datetime(2024, 5, 6, 7, 8, 9, tzinfo=datetime.timezone.utc),
+        key=uuid.uuid4(),
+        price=decimal.Decimal('12.34'),
+        color=Color.GREEN,
+    )
+    await db.insert(item)
+
+    async with db.query(Item, 'SELECT * FROM item WHERE id = :id;', {'id': 1}) as stream:
+        items = [v async for v in stream]
+    assert len(items) == 1
+    for name in Item.__struct_fields__:
+        assert getattr(items[0], name) == getattr(item, name), name
+
+
+async def test_temporal_params(db, pg):
+    await db.execute('DROP TABLE IF EXISTS t_temporal')
+    types = ('TIMESTAMPTZ', 'DATE', 'TIME') if pg else ('TEXT',) * 3
+    await db.execute('CREATE TABLE t_temporal (at {}, d {}, t {})'.format(*types))
+    at = datetime.datetime(2024, 5, 6, 7, 8, 9, tzinfo=datetime.timezone.utc)
+    params = {'at': at, 'd': at.date(), 't': at.time()}
+    await db.execute('INSERT INTO t_temporal VALUES (:at, :d, :t)', params)
+
+    # Raw rows return the stored text, models decode it
+    columns = 'at::text AS at, d::text AS d, t::text AS t' if pg else 'at, d, t'
+    row = await db.fetch_one(f'SELECT {columns} FROM t_temporal WHERE at = :at AND d = :d', params)
+    assert datetime.datetime.fromisoformat(row['at']) == at
+    assert row['d'] == '2024-05-06' and row['t'] == '07:08:09'
+
+
+async def test_uuid_params(db, pg):
+    await db.execute('DROP TABLE IF EXISTS t_uuid')
+    await db.execute(f'CREATE TABLE t_uuid (key {"UUID" if pg else "BLOB"})')
+    key = uuid.uuid4()
+    await db.execute('INSERT INTO t_uuid VALUES (:key)', {'key': key})
+
+    # Raw rows return the stored text or bytes, models decode it
+    column = 'key::text AS key' if pg else 'key'
+    row = await db.fetch_one(f'SELECT {column} FROM t_uuid WHERE key = :key', {'key': key})
+    assert row['key'] == (str(key) if pg else key.bytes)
+
+
+async def test_decimal_params(db, pg):
+    await db.execute('DROP TABLE IF EXISTS t_decimal')
+    await db.execute(f'CREATE TABLE t_decimal (price {"NUMERIC(10, 2)" if pg else "TEXT"})')
+    price = decimal.Decimal('12.34')
+    await db.execute('INSERT INTO t_decimal VALUES (:price)', {'price': price})
+
+    # Raw rows return the stored text, models decode it
+    column = 'price::text AS price' if pg else 'price'
+    row = await db.fetch_one(f'SELECT {column} FROM t_decimal WHERE price = :price', {'price': price})
+    assert decimal.Decimal(row['price']) == price
+
+
+async def test_migrate_required_column(db, pg):
+    await db.execute('DROP TABLE IF EXISTS account')
+    await db.execute('CREATE TABLE account (id BIGINT PRIMARY KEY, name TEXT NOT NULL)')
+    await db.execute('INSERT INTO account VALUES (1, :name)', {'name': 'a'})
+
+    db.register_model(AccountV2)
+    diff = await db.diff_schema(AccountV2)
+    assert diff.unsafe_added == ['email']
+    try:
+        await db.migrate(AccountV2, allow_destructive=True)
+    except pysqlx.ProgrammingError as e:
+        assert 'email' in str(e)
+    else:
+        raise AssertionError('Expected ProgrammingError')
+    # Nothing was run
+    assert (await db.diff_schema(AccountV2)).added == ['email']
+
+    await db.execute('DELETE FROM account')
+    await db.migrate(AccountV2, allow_destructive=True)
+    assert not await db.diff_schema(AccountV2)
+
+
+async def test_numeric_union(db, pg):
+    await db.execute('DROP TABLE IF EXISTS measure')
+    db.register_model(Measure)
+    await db.create_table(Measure)
+    await db.insert(Measure(id=1, value=1.0))
+    await db.insert(Measure(id=2, value=2))
+
+    # Stored as floats on both backends, so 1.0 doesn't come back as int
+    for id, value in ((1, 1.0), (2, 2.0)):
+        got = (await db.get(Measure, id)).value
+        assert got == value and isinstance(got, float), got
+
+
+async def test_unknown_extra_key(db, pg):
+    class Typo(Struct):
+        id: Annotated[int, Meta(extra={'primary_kye': True})]
+
+    try:
+        db.register_model(Typo)
+    except ValueError as e:
+        assert 'primary_kye' in str(e) and 'primary_key' in str(e)
+    else:
+        raise AssertionError('Expected ValueError')
+
+
+async def test_row_not_found(db, pg):
+    try:
+        await db.fetch_one('SELECT 1 WHERE 1 = 0')
+    except pysqlx.RowNotFoundError as e:
+        assert isinstance(e, pysqlx.ProgrammingError)
+    else:
+        raise AssertionError('Expected RowNotFoundError')
+
+
+async def test_integrity_error(db, pg):
+    await db.execute('DROP TABLE IF EXISTS t_unique')
+    await db.execute('CREATE TABLE t_unique (id BIGINT PRIMARY KEY)')
+    await db.execute('INSERT INTO t_unique VALUES (1)')
+    try:
+        await db.execute('INSERT INTO t_unique VALUES (1)')
+    except pysqlx.IntegrityError as e:
+        assert isinstance(e, pysqlx.DatabaseError)
+    else:
+        raise AssertionError('Expected IntegrityError')
+
+
+async def test_fetch(db, pg):
+    assert (await db.fetch_one('SELECT :x + 1 AS y', {'x': 41}))['y'] == 42
+    assert await db.fetch_optional('SELECT 1 WHERE 1 = :x', {'x': 2}) is None
+    rows = await db.fetch_all('SELECT 1 AS a UNION ALL SELECT 3 ORDER BY a')
+    assert [r['a'] for r in rows] == [1, 3]
+
+
+async def test_transactions(db, pg):
+    await db.execute('DROP TABLE IF EXISTS t_tx')
+    await db.execute('CREATE TABLE t_tx (id BIGINT, note TEXT)')
+    await db.execute('INSERT INTO t_tx VALUES (1, NULL)')
+
+    async with await db.begin() as tx:
+        await tx.execute('UPDATE t_tx SET note = :note WHERE id = 1', {'note': 'in tx'})
+        assert (await tx.fetch_one('SELECT note FROM t_tx'))['note'] == 'in tx'
+        try:
+            async with await tx.begin() as savepoint:
+                await savepoint.execute('DELETE FROM t_tx')
+                raise RuntimeError('roll back the savepoint')
+        except RuntimeError:
+            pass
+        assert len(await tx.fetch_all('SELECT id FROM t_tx')) == 1
+
+    try:
+        async with await db.begin() as tx:
+            await tx.execute('DELETE FROM t_tx')
+            raise RuntimeError('roll back the transaction')
+    except RuntimeError:
+        pass
+    assert [r['note'] for r in await db.fetch_all('SELECT note FROM t_tx')] == ['in tx']
+
+
+async def test_row_access(db, pg):
+    row = await db.fetch_one("SELECT 1 AS a, 'x' AS b, CAST(2.5 AS DOUBLE PRECISION) AS c")
+    assert (row[0], row[-1], row['b']) == (1, 2.5, 'x')
+    assert row[1:] == ('x', 2.5) and tuple(row) == (1, 'x', 2.5) and len(row) == 3
+    assert 'a' in row and 'z' not in row and 0 not in row
+    assert row.get('z', 'default') == 'default' and row.get(5) is None
+    assert row.keys() == ['a', 'b', 'c'] and dict(row.items()) == {'a': 1, 'b': 'x', 'c': 2.5}
+    for key in ('z', 5):
+        try:
+            row[key]
+        except (KeyError, IndexError):
+            pass
+        else:
+            raise AssertionError(f'Expected an error for {key!r}')
+
+
+async def test_register_model(db, pg):
+    schema = db.register_model(Product)
+    assert schema.model is Product and schema.table_name == 'product' and schema.primary_key == 'id'
+    assert [(f.name, f.nullable) for f in schema.fields] == [
+        ('id', False),
+        ('name', False),
+        ('tags', False),
+        ('note', True),
+    ]
+
+
+async def test_create_table(db, pg):
+    await db.execute('DROP TABLE IF EXISTS product')
+    [create] = db.ddl(Product)
+    assert create.startswith('CREATE TABLE IF NOT EXISTS "product"'), create
+    assert f'"tags" {"JSONB" if pg else "BLOB"} NOT NULL' in create, create
+    await db.create_table(Product)
+    await db.create_table(Product)
+    assert not await db.diff_schema(Product)
+
+
+async def test_insert(db, pg):
+    await db.execute('DELETE FROM product')
+    result = await db.insert(Product(id=1, name='a', tags=['x']))
+    assert result.rows_affected == 1
+    assert await db.insert(Product(id=2, name='b', tags=[]), returning=True) == 2
+    products = [Product(id=i, name=str(i), tags=[str(i)]) for i in range(3, 6)]
+    assert await db.insert_many(products, returning=True) == [3, 4, 5]
+    assert (await db.insert_many([])).rows_affected == 0
+
+    rows = await db.fetch_all('SELECT id, name FROM product ORDER BY id')
+    assert [(r['id'], r['name']) for r in rows] == [(1, 'a'), (2, 'b'), (3, '3'), (4, '4'), (5, '5')]
+
+
+async def test_query_models(db, pg):
+    await db.execute('DELETE FROM product')
+    await db.insert_many([Product(id=i, name=str(i), tags=[str(i)], note='n' if i % 2 else None) for i in range(1, 5)])
+
+    stream = db.query(Product, 'SELECT * FROM product WHERE id > :id ORDER BY id', {'id': 1})
+    first = await stream.next()
+    assert isinstance(first, Product) and fields(first) == (2, '2', ['2'], None)
+    assert [v.id for v in await stream.fetch_many(5)] == [3, 4]
+    assert await stream.next() is None
+
+
+async def test_crud(db, pg):
+    await db.execute('DELETE FROM product')
+    await db.insert(Product(id=1, name='a', tags=['x']))
+    product = await db.get(Product, 1)
+    assert fields(product) == (1, 'a', ['x'], None)
+    assert await db.get(Product, 2) is None
+
+    product.note, product.name = 'updated', 'ignored'
+    assert (await db.update(product, ['note'])).rows_affected == 1
+    assert fields(await db.get(Product, 1)) == (1, 'a', ['x'], 'updated')
+
+    await db.upsert(Product(id=1, name='b', tags=[]))
+    await db.upsert(Product(id=2, name='c', tags=[]))
+    assert [fields(await db.get(Product, i)) for i in (1, 2)] == [(1, 'b', [], None), (2, 'c', [], None)]
+
+    assert (await db.delete(Product, 2)).rows_affected == 1
+    assert await db.get(Product, 2) is None
+
+
+async def test_migrate(db, pg):
+    await db.execute('DROP TABLE IF EXISTS note')
+    db.register_model(Note)
+    await db.create_table(Note)
+    await db.insert(Note(id=1, text='a'))
+
+    db.register_model(NoteV2)
+    diff = await db.diff_schema(NoteV2)
+    assert diff.added == ['author'] and diff.unsafe_added == [] and len(diff.missing_indexes) == 1
+    statements = await db.migrate(NoteV2)
+    assert len(statements) == 2, statements
+    assert not await db.diff_schema(NoteV2)
+    assert fields(await db.get(NoteV2, 1)) == (1, 'a', None)
+
+
+async def test_enum_fields(db, pg):
+    db.register_model(Item)
+    await db.create_table(Item)
+    await db.execute('DELETE FROM item')
+    for id, color in ((1, Color.RED), (2, Color.GREEN), (3, Color.RED)):
+        await db.insert(Item(
+            id=id,
+            data={},
+            at=datetime.datetime.now(datetime.timezone.utc),
+            key=uuid.uuid4(),
+            price=decimal.Decimal(id),
+            color=color,
+        ))
+
+    query = 'SELECT * FROM item WHERE color = :color ORDER BY id'
+    async with db.query(Item, query, {'color': Color.RED}) as stream:
+        assert [(v.id, v.color) async for v in stream] == [(1, Color.RED), (3, Color.RED)]
+
+
+TESTS = [
+    test_params,
+    test_null_params,
+    test_sync_methods_during_await,
+    test_close_pending_stream,
+    test_model_query,
+    test_temporal_params,
+    test_uuid_params,
+    test_decimal_params,
+    test_migrate_required_column,
+    test_numeric_union,
+    test_unknown_extra_key,
+    test_row_not_found,
+    test_integrity_error,
+    test_fetch,
+    test_transactions,
+    test_row_access,
+    test_register_model,
+    test_create_table,
+    test_insert,
+    test_query_models,
+    test_crud,
+    test_migrate,
+    test_enum_fields,
+]
+
+
+async def main():
+    with tempfile.TemporaryDirectory() as tmp:
+        urls = [f'sqlite:{tmp}/test.db?mode=rwc'] + ([POSTGRES_URL] if POSTGRES_URL else [])
+        for url in urls:
+            db = pysqlx.SqlxDb(url)
+            db.register_model(ExampleModel)
+            for test in TESTS:
+                await test(db, url == POSTGRES_URL)
+                print(f'{url.split(":")[0]} {test.__name__} ok')
+
+
+asyncio.run(main())